@@ -0,0 +1,78 @@
+use failure::Error;
+use printpdf::Mm;
+use crate::resources::Resources;
+use crate::span::Span;
+use std::str::FromStr;
+
+/// How the lines of a block of text are placed between its margins
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextAlignment {
+    Left,
+    Right,
+    Center,
+    /// Lines are stretched to fill the whole width by widening the spaces
+    /// between words. The last line of a paragraph is left aligned.
+    Justify,
+}
+
+impl FromStr for TextAlignment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "left" => Ok(TextAlignment::Left),
+            "right" => Ok(TextAlignment::Right),
+            "center" => Ok(TextAlignment::Center),
+            "justify" => Ok(TextAlignment::Justify),
+            _ => Err(format_err!(
+                "Unknown alignment {:?}, expected left, right, center or justify",
+                s
+            )),
+        }
+    }
+}
+
+/// Aligns a line of `width` that starts at the left edge of the text. `wrapped`
+/// tells if the line was broken because the next word didn't fit, as only those
+/// lines are justified.
+pub fn align_line(
+    resources: &Resources,
+    line: &mut Vec<Span>,
+    width: Mm,
+    alignment: TextAlignment,
+    wrapped: bool,
+) {
+    // The space before a word that was moved to the next line isn't seen
+    while line.last().map(Span::is_space).unwrap_or(false) {
+        line.pop();
+    }
+    let extra = line
+        .iter()
+        .fold(width, |acc, span| acc - span.width(resources));
+    if extra.0 <= 0.0 {
+        return;
+    }
+
+    match alignment {
+        TextAlignment::Left => {}
+        TextAlignment::Right => line.insert(0, Span::gap(extra)),
+        TextAlignment::Center => line.insert(0, Span::gap(extra / 2.0)),
+        TextAlignment::Justify if wrapped => {
+            let spaces = line.iter().filter(|span| span.is_space()).count();
+            if spaces == 0 {
+                return;
+            }
+            let stretch = extra / spaces as f64;
+            let mut justified = Vec::with_capacity(line.len() + spaces);
+            for span in line.drain(..) {
+                let space = span.is_space();
+                justified.push(span);
+                if space {
+                    justified.push(Span::gap(stretch));
+                }
+            }
+            *line = justified;
+        }
+        TextAlignment::Justify => {}
+    }
+}
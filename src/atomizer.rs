@@ -1,14 +1,43 @@
-use crate::cmark::{Event as ParseEvent, Parser, Tag};
+use failure::Error;
+use crate::cmark::{Alignment, CodeBlockKind, Event as ParseEvent, Parser, Tag};
+use crate::code::CodeBlockInfo;
+use crate::heading::{Anchors, Heading};
+use crate::highlight::{highlight, Token};
+use crate::include::Include;
+use crate::resources::Loader;
 use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use crate::style::{Class, Style};
-use crate::util::{slice_cow_from_idx, slice_cow_till_idx};
+use crate::util::{cow_from_cmark, slice_cow_from_idx, slice_cow_till_idx};
 
-pub struct Atomizer<'src> {
+pub struct Atomizer<'src, 'l> {
     state: AtomizerState<'src>,
     parser: Parser<'src>,
+    /// Reads the files that are included into code blocks
+    loader: &'l dyn Loader,
+    /// Events that were read ahead of time from the parser
+    lookahead: VecDeque<ParseEvent<'src>>,
+    anchors: Anchors,
+    current_heading: Option<Heading>,
+    /// The number of items of every list that is being split, from the
+    /// outermost inwards
+    list_items: Vec<usize>,
+    /// The numbers given to footnotes, in the order they were first seen
+    footnotes: HashMap<String, usize>,
     current_style: Style,
     is_code: bool,
+    /// The info string of the code block that is being split, with the line
+    /// numbers of its included file filled in
+    code_info: Option<CodeBlockInfo>,
+    /// The highlighted tokens of the code block that is being split
+    code_tokens: VecDeque<(String, Option<Token>)>,
+    /// The token the text that is being split belongs to
+    current_token: Option<Token>,
+    /// Whether the text that is being split is an inline code span
+    is_inline_code: bool,
     is_alt_text: bool,
+    /// How deeply the block quote that is being split is nested
+    quote_level: usize,
 }
 
 #[derive(Debug)]
@@ -23,6 +52,10 @@ enum AtomizerState<'src> {
 pub enum Event<'src> {
     Atom(Atom<'src>),
     Break(Break),
+    /// The place where the table of contents should be put
+    TableOfContents,
+    /// Marks the list item it is in as a task, which is done if `true`
+    TaskListMarker(bool),
     StartBlock(BlockTag),
     EndBlock(BlockTag),
 }
@@ -30,7 +63,11 @@ pub enum Event<'src> {
 #[derive(Debug)]
 pub enum Atom<'src> {
     Text { text: Cow<'src, str>, style: Style },
-    Image { uri: Cow<'src, str> },
+    /// An image, along with the destination of the link around it, if any
+    Image {
+        uri: Cow<'src, str>,
+        link: Option<String>,
+    },
 }
 
 #[derive(Debug)]
@@ -44,22 +81,35 @@ pub enum Break {
 
 #[derive(Debug)]
 pub enum BlockTag {
+    Heading(Heading),
     BlockQuote,
-    CodeBlock,
-    List(Option<usize>),
+    /// A code block, along with what its info string says about it
+    CodeBlock(CodeBlockInfo),
+    /// A list, along with the number of its first item if it is ordered, and
+    /// the number of its items
+    List(Option<usize>, usize),
     ListItem,
+    Table(Vec<Alignment>),
+    TableHead,
+    TableRow,
+    TableCell,
+    /// The definition of the footnote with the given number
+    Footnote(usize),
 }
 
-impl<'src> Iterator for Atomizer<'src> {
+impl<'src, 'l> Iterator for Atomizer<'src, 'l> {
     type Item = Event<'src>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let state = ::std::mem::replace(&mut self.state, AtomizerState::Parsing);
             let (next_event, next_state) = match state {
-                AtomizerState::Parsing => match self.parser.next() {
-                    Some(parser_event) => self.parse_event(parser_event),
-                    None => break,
+                AtomizerState::Parsing => match self.code_tokens.pop_front() {
+                    Some((text, token)) => self.start_token(text, token),
+                    None => match self.next_parse_event() {
+                        Some(parser_event) => self.parse_event(parser_event),
+                        None => break,
+                    },
                 },
                 AtomizerState::Splitting(text) => self.split_text(text),
             };
@@ -72,19 +122,178 @@ impl<'src> Iterator for Atomizer<'src> {
     }
 }
 
-impl<'src> Atomizer<'src> {
-    pub fn new(parser: Parser<'src>) -> Self {
+impl<'src, 'l> Atomizer<'src, 'l> {
+    pub fn new(parser: Parser<'src>, loader: &'l dyn Loader) -> Self {
         Atomizer {
             state: AtomizerState::Parsing,
-            parser: parser,
+            parser,
+            loader,
+            lookahead: VecDeque::new(),
+            anchors: Anchors::default(),
+            current_heading: None,
+            list_items: Vec::new(),
+            footnotes: HashMap::new(),
             current_style: Style::default(),
             is_code: false,
+            code_info: None,
+            code_tokens: VecDeque::new(),
+            current_token: None,
+            is_inline_code: false,
             is_alt_text: false,
+            quote_level: 0,
+        }
+    }
+
+    fn next_parse_event(&mut self) -> Option<ParseEvent<'src>> {
+        self.lookahead.pop_front().or_else(|| self.parser.next())
+    }
+
+    /// Reads ahead to the first event that `is_end` matches, and returns the
+    /// text in between. The boolean is `false` if there was anything other
+    /// than text before the end.
+    fn peek_text<F: Fn(&ParseEvent) -> bool>(&mut self, is_end: F) -> (String, bool) {
+        let mut text = String::new();
+        let mut only_text = true;
+        let mut idx = 0;
+        loop {
+            if idx == self.lookahead.len() {
+                match self.parser.next() {
+                    Some(event) => self.lookahead.push_back(event),
+                    None => break,
+                }
+            }
+            match self.lookahead[idx] {
+                ref event if is_end(event) => break,
+                ParseEvent::Text(ref t) | ParseEvent::Code(ref t) => text.push_str(t),
+                ParseEvent::SoftBreak | ParseEvent::HardBreak => {
+                    text.push(' ');
+                    only_text = false;
+                }
+                _ => only_text = false,
+            }
+            idx += 1;
+        }
+        (text, only_text)
+    }
+
+    /// Reads the text of the code block that was just started, replaces it
+    /// with the files that it includes, and splits it into highlighted tokens
+    /// if its language is known
+    fn read_code_block(&mut self, info: &mut CodeBlockInfo) {
+        let (mut code, only_text) =
+            self.peek_text(|e| matches!(e, ParseEvent::End(Tag::CodeBlock(_))));
+        if !only_text {
+            return;
+        }
+
+        let mut replaced = false;
+        if let Some(ref include) = info.include {
+            match self.include(include) {
+                Ok((text, first_line)) => {
+                    code = text;
+                    info.first_line.get_or_insert(first_line);
+                    replaced = true;
+                }
+                Err(e) => warn!("Could not include {:?}: {}", include.path, e),
+            }
+        } else if code.contains("{{#include") {
+            code = code
+                .lines()
+                .map(|line| match Include::parse_directive(line) {
+                    Some(include) => match self.include(&include) {
+                        Ok((text, _)) => text,
+                        Err(e) => {
+                            warn!("Could not include {:?}: {}", include.path, e);
+                            format!("{}\n", line)
+                        }
+                    },
+                    None => format!("{}\n", line),
+                })
+                .collect();
+            replaced = true;
+        }
+
+        let tokens = match info.language {
+            Some(ref language) => {
+                let tokens = highlight(language, &code);
+                if tokens.is_none() {
+                    debug!("No syntax highlighting for {:?}", language);
+                }
+                tokens
+            }
+            None => None,
+        };
+        if tokens.is_some() || replaced {
+            while let Some(ParseEvent::Text(_)) = self.lookahead.front() {
+                self.lookahead.pop_front();
+            }
+            self.code_tokens = tokens.unwrap_or_else(|| vec![(code, None)]).into();
+        }
+    }
+
+    /// Reads the lines of a file that are included into a code block, and
+    /// the number of the first of them
+    fn include(&self, include: &Include) -> Result<(String, usize), Error> {
+        let text = self.loader.load_text(&include.path)?;
+        include.select(&text)
+    }
+
+    /// The class of the text of the block quote that is being split. Quotes
+    /// that are nested more than 255 levels deep share the deepest level.
+    fn quote_class(&self) -> Class {
+        Class::BlockQuote(self.quote_level.min(u8::MAX as usize) as u8)
+    }
+
+    /// Reads ahead to the end of the list that was just started, and counts
+    /// its items, leaving out those of the lists nested inside of it
+    fn count_list_items(&mut self) -> usize {
+        let mut items = 0;
+        let mut depth = 0;
+        let mut idx = 0;
+        loop {
+            if idx == self.lookahead.len() {
+                match self.parser.next() {
+                    Some(event) => self.lookahead.push_back(event),
+                    None => break,
+                }
+            }
+            match self.lookahead[idx] {
+                ParseEvent::Start(Tag::List(_)) => depth += 1,
+                ParseEvent::End(Tag::List(_)) if depth == 0 => break,
+                ParseEvent::End(Tag::List(_)) => depth -= 1,
+                ParseEvent::Start(Tag::Item) if depth == 0 => items += 1,
+                _ => {}
+            }
+            idx += 1;
         }
+        items
+    }
+
+    /// Splits the text of a highlighted token, in the style of the token
+    fn start_token(
+        &mut self,
+        text: String,
+        token: Option<Token>,
+    ) -> (Option<Event<'src>>, AtomizerState<'src>) {
+        if let Some(previous) = self.current_token.take() {
+            self.current_style.remove(&Class::Token(previous));
+        }
+        if let Some(token) = token {
+            self.current_style.insert(Class::Token(token));
+            self.current_token = Some(token);
+        }
+        (None, AtomizerState::Splitting(text.into()))
+    }
+
+    /// The number of the footnote with the given label. Footnotes are numbered
+    /// in the order that they are referenced or defined in.
+    fn footnote_number(&mut self, label: &str) -> usize {
+        let next_number = self.footnotes.len() + 1;
+        *self.footnotes.entry(label.to_string()).or_insert(next_number)
     }
 
     fn split_text(&mut self, text: Cow<'src, str>) -> (Option<Event<'src>>, AtomizerState<'src>) {
-        if text.len() == 0 || self.is_alt_text {
+        if text.is_empty() || self.is_alt_text {
             return (None, AtomizerState::Parsing);
         }
         match text.chars().next().expect("string len must be > 0") {
@@ -145,39 +354,76 @@ impl<'src> Atomizer<'src> {
         &mut self,
         event: ParseEvent<'src>,
     ) -> (Option<Event<'src>>, AtomizerState<'src>) {
+        // Inline code spans are a single event, so their style is only removed
+        // once all of their text has been split apart
+        if self.is_inline_code {
+            self.current_style.remove(&Class::Code);
+            self.is_inline_code = false;
+        }
         match event {
             ParseEvent::Start(Tag::Strong) => self.current_style.insert(Class::Strong),
             ParseEvent::End(Tag::Strong) => self.current_style.remove(&Class::Strong),
             ParseEvent::Start(Tag::Emphasis) => self.current_style.insert(Class::Emphasis),
             ParseEvent::End(Tag::Emphasis) => self.current_style.remove(&Class::Emphasis),
-            ParseEvent::Start(Tag::Code) => self.current_style.insert(Class::Code),
-            ParseEvent::End(Tag::Code) => self.current_style.remove(&Class::Code),
+            ParseEvent::Start(Tag::Strikethrough) => self.current_style.insert(Class::Strikethrough),
+            ParseEvent::End(Tag::Strikethrough) => self.current_style.remove(&Class::Strikethrough),
+            ParseEvent::Code(text) => {
+                self.current_style.insert(Class::Code);
+                self.is_inline_code = true;
+                return (None, AtomizerState::Splitting(cow_from_cmark(text)));
+            }
+            ParseEvent::Start(Tag::Link(_link_type, destination, _title)) => {
+                self.current_style.insert(Class::Link(destination.into_string()))
+            }
+            ParseEvent::End(Tag::Link(_link_type, destination, _title)) => {
+                self.current_style.remove(&Class::Link(destination.into_string()))
+            }
 
-            ParseEvent::Start(Tag::Rule) => {
+            ParseEvent::Rule => {
                 return (
                     Some(Event::Break(Break::HorizontalRule)),
                     AtomizerState::Parsing,
                 )
             }
-            ParseEvent::End(Tag::Rule) => {}
 
-            ParseEvent::Start(Tag::Header(size)) => {
-                self.current_style.insert(Class::Heading(size as u8))
+            ParseEvent::Start(Tag::Heading(size)) => {
+                self.current_style.insert(Class::Heading(size as u8));
+                let (text, _) = self.peek_text(|e| matches!(e, ParseEvent::End(Tag::Heading(_))));
+                let heading = Heading {
+                    level: size as u8,
+                    anchor: self.anchors.anchor(&text),
+                    text,
+                };
+                self.current_heading = Some(heading.clone());
+                return (
+                    Some(Event::StartBlock(BlockTag::Heading(heading))),
+                    AtomizerState::Parsing,
+                );
             }
-            ParseEvent::End(Tag::Header(size)) => {
+            ParseEvent::End(Tag::Heading(size)) => {
                 self.current_style.remove(&Class::Heading(size as u8));
-                return (Some(Event::Break(Break::Paragraph)), AtomizerState::Parsing);
+                if let Some(heading) = self.current_heading.take() {
+                    return (
+                        Some(Event::EndBlock(BlockTag::Heading(heading))),
+                        AtomizerState::Parsing,
+                    );
+                }
             }
 
             ParseEvent::Start(Tag::List(first_number)) => {
+                let first_number = first_number.map(|n| n as usize);
+                let items = self.count_list_items();
+                self.list_items.push(items);
                 return (
-                    Some(Event::StartBlock(BlockTag::List(first_number))),
+                    Some(Event::StartBlock(BlockTag::List(first_number, items))),
                     AtomizerState::Parsing,
                 )
             }
             ParseEvent::End(Tag::List(first_number)) => {
+                let first_number = first_number.map(|n| n as usize);
+                let items = self.list_items.pop().unwrap_or(0);
                 return (
-                    Some(Event::EndBlock(BlockTag::List(first_number))),
+                    Some(Event::EndBlock(BlockTag::List(first_number, items))),
                     AtomizerState::Parsing,
                 )
             }
@@ -194,21 +440,112 @@ impl<'src> Atomizer<'src> {
                     AtomizerState::Parsing,
                 )
             }
+            ParseEvent::TaskListMarker(checked) => {
+                return (Some(Event::TaskListMarker(checked)), AtomizerState::Parsing)
+            }
 
             ParseEvent::Start(Tag::BlockQuote) => {
+                self.current_style.remove(&self.quote_class());
+                self.quote_level += 1;
+                self.current_style.insert(self.quote_class());
                 return (
                     Some(Event::StartBlock(BlockTag::BlockQuote)),
                     AtomizerState::Parsing,
                 )
             }
             ParseEvent::End(Tag::BlockQuote) => {
+                self.current_style.remove(&self.quote_class());
+                self.quote_level -= 1;
+                if self.quote_level > 0 {
+                    self.current_style.insert(self.quote_class());
+                }
                 return (
                     Some(Event::EndBlock(BlockTag::BlockQuote)),
                     AtomizerState::Parsing,
                 )
             }
 
-            ParseEvent::Text(text) => return (None, AtomizerState::Splitting(text)),
+            ParseEvent::Start(Tag::Table(alignments)) => {
+                return (
+                    Some(Event::StartBlock(BlockTag::Table(alignments))),
+                    AtomizerState::Parsing,
+                )
+            }
+            ParseEvent::End(Tag::Table(alignments)) => {
+                return (
+                    Some(Event::EndBlock(BlockTag::Table(alignments))),
+                    AtomizerState::Parsing,
+                )
+            }
+
+            ParseEvent::Start(Tag::TableHead) => {
+                return (
+                    Some(Event::StartBlock(BlockTag::TableHead)),
+                    AtomizerState::Parsing,
+                )
+            }
+            ParseEvent::End(Tag::TableHead) => {
+                return (
+                    Some(Event::EndBlock(BlockTag::TableHead)),
+                    AtomizerState::Parsing,
+                )
+            }
+
+            ParseEvent::Start(Tag::TableRow) => {
+                return (
+                    Some(Event::StartBlock(BlockTag::TableRow)),
+                    AtomizerState::Parsing,
+                )
+            }
+            ParseEvent::End(Tag::TableRow) => {
+                return (
+                    Some(Event::EndBlock(BlockTag::TableRow)),
+                    AtomizerState::Parsing,
+                )
+            }
+
+            ParseEvent::Start(Tag::TableCell) => {
+                return (
+                    Some(Event::StartBlock(BlockTag::TableCell)),
+                    AtomizerState::Parsing,
+                )
+            }
+            ParseEvent::End(Tag::TableCell) => {
+                return (
+                    Some(Event::EndBlock(BlockTag::TableCell)),
+                    AtomizerState::Parsing,
+                )
+            }
+
+            ParseEvent::FootnoteReference(label) => {
+                let mut style = self.current_style.clone();
+                style.insert(Class::Superscript);
+                let text = self.footnote_number(&label).to_string().into();
+                return (
+                    Some(Event::Atom(Atom::Text { text, style })),
+                    AtomizerState::Parsing,
+                );
+            }
+            ParseEvent::Start(Tag::FootnoteDefinition(label)) => {
+                self.current_style.insert(Class::Note);
+                let number = self.footnote_number(&label);
+                return (
+                    Some(Event::StartBlock(BlockTag::Footnote(number))),
+                    AtomizerState::Parsing,
+                );
+            }
+            ParseEvent::End(Tag::FootnoteDefinition(label)) => {
+                self.current_style.remove(&Class::Note);
+                let number = self.footnote_number(&label);
+                return (
+                    Some(Event::EndBlock(BlockTag::Footnote(number))),
+                    AtomizerState::Parsing,
+                );
+            }
+
+            ParseEvent::Text(text) => {
+                return (None, AtomizerState::Splitting(cow_from_cmark(text)))
+            }
 
             ParseEvent::Html(html) => {
                 use quick_xml::{Reader, events::Event as XMLEvent};
@@ -236,36 +573,55 @@ impl<'src> Atomizer<'src> {
                 }
             }
 
-            ParseEvent::Start(Tag::Image(uri, _title)) => {
+            ParseEvent::Start(Tag::Image(_link_type, uri, _title)) => {
                 self.is_alt_text = true;
                 return (
-                    Some(Event::Atom(Atom::Image { uri })),
+                    Some(Event::Atom(Atom::Image {
+                        uri: cow_from_cmark(uri),
+                        link: self.current_style.link().map(String::from),
+                    })),
                     AtomizerState::Parsing,
                 );
             }
-            ParseEvent::End(Tag::Image(_url, _title)) => {
+            ParseEvent::End(Tag::Image(_link_type, _url, _title)) => {
                 self.is_alt_text = false;
             }
 
-            ParseEvent::Start(Tag::CodeBlock(_src_type)) => {
+            ParseEvent::Start(Tag::CodeBlock(kind)) => {
                 self.is_code = true;
                 self.current_style.insert(Class::Code);
-                self.current_style.insert(Class::Code);
+                let mut info = code_block_info(kind);
+                self.read_code_block(&mut info);
+                self.code_info = Some(info.clone());
                 return (
-                    Some(Event::StartBlock(BlockTag::CodeBlock)),
+                    Some(Event::StartBlock(BlockTag::CodeBlock(info))),
                     AtomizerState::Parsing,
                 );
             }
-            ParseEvent::End(Tag::CodeBlock(_)) => {
+            ParseEvent::End(Tag::CodeBlock(kind)) => {
+                let info = self.code_info.take().unwrap_or_else(|| code_block_info(kind));
                 self.is_code = false;
                 self.current_style.remove(&Class::Code);
+                if let Some(token) = self.current_token.take() {
+                    self.current_style.remove(&Class::Token(token));
+                }
                 return (
-                    Some(Event::EndBlock(BlockTag::CodeBlock)),
+                    Some(Event::EndBlock(BlockTag::CodeBlock(info))),
                     AtomizerState::Parsing,
                 );
             }
 
-            ParseEvent::Start(Tag::Paragraph) => {}
+            ParseEvent::Start(Tag::Paragraph) => {
+                let (text, only_text) = self.peek_text(|e| e == &ParseEvent::End(Tag::Paragraph));
+                if only_text && text.trim() == "[[TOC]]" {
+                    while let Some(event) = self.lookahead.pop_front() {
+                        if event == ParseEvent::End(Tag::Paragraph) {
+                            break;
+                        }
+                    }
+                    return (Some(Event::TableOfContents), AtomizerState::Parsing);
+                }
+            }
             ParseEvent::End(Tag::Paragraph) => {
                 return (Some(Event::Break(Break::Paragraph)), AtomizerState::Parsing)
             }
@@ -276,9 +632,55 @@ impl<'src> Atomizer<'src> {
             ParseEvent::HardBreak => {
                 return (Some(Event::Break(Break::Line)), AtomizerState::Parsing)
             }
-
-            _ => {}
         };
         (None, AtomizerState::Parsing)
     }
 }
+
+fn code_block_info(kind: CodeBlockKind) -> CodeBlockInfo {
+    match kind {
+        CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(&info),
+        CodeBlockKind::Indented => CodeBlockInfo::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::SimpleLoader;
+    use std::path::PathBuf;
+
+    /// The quote levels of the text atoms of `markdown`
+    fn quote_levels(markdown: &str) -> Vec<(String, Option<u8>)> {
+        let loader = SimpleLoader::new(PathBuf::new());
+        Atomizer::new(Parser::new(markdown), &loader)
+            .filter_map(|event| match event {
+                Event::Atom(Atom::Text { text, style }) => {
+                    Some((text.into_owned(), style.quote_level()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nests_quotes() {
+        assert_eq!(
+            quote_levels("> one\n>> two\n\nthree"),
+            vec![
+                ("one".into(), Some(1)),
+                ("two".into(), Some(2)),
+                ("three".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_the_level_of_deeply_nested_quotes() {
+        let markdown = format!("{} deep\n\nafter", ">".repeat(300));
+        assert_eq!(
+            quote_levels(&markdown),
+            vec![("deep".into(), Some(255)), ("after".into(), None)]
+        );
+    }
+}
@@ -22,6 +22,60 @@ struct Cli {
     #[structopt(long = "out", short = "o")]
     output_file: Option<String>,
 
+    /// Put a table of contents at the start of the document. Without this flag,
+    /// a table of contents is only generated where the markdown contains `[[TOC]]`.
+    #[structopt(long = "toc")]
+    toc: bool,
+
+    /// Number the pages in their footer, as "Page 1 of 10".
+    #[structopt(long = "page-numbers")]
+    page_numbers: bool,
+
+    /// Don't parse `~~strikethrough~~` text.
+    #[structopt(long = "no-strikethrough")]
+    no_strikethrough: bool,
+
+    /// Don't parse `- [ ]` and `- [x]` list items as tasks.
+    #[structopt(long = "no-task-lists")]
+    no_task_lists: bool,
+
+    /// Turn task list items into checkboxes that can be ticked in a PDF viewer.
+    #[structopt(long = "checkboxes")]
+    checkboxes: bool,
+
+    /// Turn straight quotes into curly quotes, and `--`, `---` and `...` into
+    /// dashes and ellipses.
+    #[structopt(long = "smart-punctuation")]
+    smart_punctuation: bool,
+
+    /// How to align body text: left, right, center or justify.
+    #[structopt(long = "align", default_value = "left")]
+    align: mdproof::TextAlignment,
+
+    /// Choose line breaks for each paragraph as a whole, which gives more even
+    /// lines than filling one line at a time, but takes longer.
+    #[structopt(long = "optimal-line-breaks")]
+    optimal_line_breaks: bool,
+
+    /// Hyphenate words that don't fit at the end of a line, using the patterns
+    /// of the given language. Only `en-us` is built in.
+    #[structopt(long = "hyphenate")]
+    hyphenate: Option<String>,
+
+    /// Shrink the font of code blocks whose lines are too wide for the page,
+    /// instead of wrapping the lines.
+    #[structopt(long = "shrink-code")]
+    shrink_code: bool,
+
+    /// The colors of highlighted code blocks: light, solarized, greyscale or
+    /// none.
+    #[structopt(long = "syntax-theme", default_value = "light")]
+    syntax_theme: String,
+
+    /// Set the text of block quotes in italics.
+    #[structopt(long = "italic-quotes")]
+    italic_quotes: bool,
+
     #[structopt(flatten)]
     verbosity: Verbosity,
 }
@@ -36,7 +90,42 @@ main!(|args: Cli, log_level: verbosity| {
         .to_str()
         .ok_or(format_err!("Could not convert output path to string"))?;
 
-    let mut cfg = mdproof::Config::default();
+    let mut cfg = mdproof::Config {
+        table_of_contents: args.toc,
+        strikethrough: !args.no_strikethrough,
+        task_lists: !args.no_task_lists,
+        task_list_checkboxes: args.checkboxes,
+        smart_punctuation: args.smart_punctuation,
+        text_alignment: args.align,
+        line_breaking: if args.optimal_line_breaks {
+            mdproof::LineBreaking::Optimal
+        } else {
+            mdproof::LineBreaking::Greedy
+        },
+        code_overflow: if args.shrink_code {
+            mdproof::CodeOverflow::Shrink
+        } else {
+            mdproof::CodeOverflow::Wrap
+        },
+        quote_italic: args.italic_quotes,
+        ..Default::default()
+    };
+
+    if args.page_numbers {
+        cfg.footer = mdproof::PageTemplate {
+            center: "Page {page} of {pages}".into(),
+            ..Default::default()
+        };
+    }
+
+    cfg.syntax_theme = match args.syntax_theme.as_str() {
+        "none" => None,
+        theme => Some(theme.parse()?),
+    };
+
+    if let Some(ref language) = args.hyphenate {
+        cfg.hyphenation = Some(mdproof::Hyphenator::language(language)?);
+    }
 
     let mut markdown = String::new();
     let mut generated_title = None;
@@ -0,0 +1,300 @@
+use printpdf::Mm;
+use crate::include::{parse_lines, Include};
+use crate::resources::Resources;
+use crate::span::Span;
+use crate::style::{Class, Style};
+use crate::table::line_width;
+use crate::util::{overlong_split, scale_from_style, width_of_text};
+
+/// What is done with lines of code that are wider than the page
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CodeOverflow {
+    /// Lines are wrapped at the right margin. The lines they are continued on
+    /// start with `Config::code_continuation_marker`.
+    Wrap,
+    /// The font of the whole code block is made smaller until its longest line
+    /// fits, down to `Config::code_min_font_scale`. Lines that still don't fit
+    /// are wrapped.
+    Shrink,
+}
+
+/// What the info string of a fenced code block says about it, as in
+/// `rust {linenos, hl=3-5}` or `rust file=src/lib.rs lines=10-40`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CodeBlockInfo {
+    /// The language the code is written in, which is the first word of the
+    /// info string, unless that is an option
+    pub language: Option<String>,
+    /// Put the number of every line in front of it (`linenos`)
+    pub line_numbers: bool,
+    /// The number of the first line (`start=10`). Without it, lines are
+    /// numbered from 1, or from the first line that is included from a file.
+    pub first_line: Option<usize>,
+    /// The numbers of the lines that are highlighted, as inclusive ranges
+    /// (`hl=3-5,8`). These are the numbers the lines are shown with, so they
+    /// count from `first_line`, or from the first line included from a file.
+    pub highlighted: Vec<(usize, usize)>,
+    /// The file that the code is read from, instead of the text of the block
+    /// (`file=src/lib.rs lines=10-40` or `file=src/lib.rs anchor=name`)
+    pub include: Option<Include>,
+}
+
+impl CodeBlockInfo {
+    /// Reads an info string. Its options can be put in braces, and are
+    /// separated by commas or spaces. Unknown options are ignored.
+    pub fn parse(info: &str) -> Self {
+        let mut code_info = Self::default();
+        let mut include = Include::default();
+        let mut words = info
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .peekable();
+        // Info strings can also start with an option, as in `file=src/lib.rs`
+        if let Some(language) = words
+            .peek()
+            .filter(|word| !word.starts_with('{') && !word.contains('='))
+        {
+            code_info.language = Some(language.to_string());
+            words.next();
+        }
+
+        // The option that a value without a name belongs to, as in the `8` of
+        // `hl=3-5,8`
+        let mut last_key = "";
+        for word in words {
+            let word = word.trim_matches(|c| ['{', '}', '[', ']', '"', '\''].contains(&c));
+            let (key, value) = match word.find('=') {
+                Some(idx) => (&word[..idx], Some(&word[idx + 1..])),
+                None if word.starts_with(|c: char| c.is_ascii_digit()) => (last_key, Some(word)),
+                None => (word, None),
+            };
+            let value = value.map(|value| {
+                value.trim_matches(|c| ['[', ']', '"', '\''].contains(&c))
+            });
+            match (key, value) {
+                ("linenos", None) | ("linenums", None) => code_info.line_numbers = true,
+                ("linenos", Some(value)) | ("linenums", Some(value)) => {
+                    code_info.line_numbers = value != "false"
+                }
+                ("start", Some(value)) | ("linenostart", Some(value)) => match value.parse() {
+                    Ok(first_line) => code_info.first_line = Some(first_line),
+                    Err(_) => warn!("{:?} is not a line number", value),
+                },
+                ("hl", Some(value)) | ("hl_lines", Some(value)) => match parse_range(value) {
+                    Some(range) => code_info.highlighted.push(range),
+                    None => warn!("{:?} is not a line or a range of lines", value),
+                },
+                ("file", Some(value)) => include.path = value.to_string(),
+                ("lines", Some(value)) => match parse_lines(value) {
+                    Some(lines) => include.lines = lines,
+                    None => warn!("{:?} is not a line or a range of lines", value),
+                },
+                ("anchor", Some(value)) => include.anchor = Some(value.to_string()),
+                _ => {}
+            }
+            last_key = key;
+        }
+        if !include.path.is_empty() {
+            code_info.include = Some(include);
+        }
+        code_info
+    }
+
+    /// Whether the line with the given number is highlighted
+    pub fn is_highlighted(&self, number: usize) -> bool {
+        self.highlighted
+            .iter()
+            .any(|&(start, end)| start <= number && number <= end)
+    }
+}
+
+/// Reads a line number, like `3`, or an inclusive range of them, like `3-5`
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut bounds = range.splitn(2, '-');
+    let start = bounds.next()?.parse().ok()?;
+    let end = match bounds.next() {
+        Some(end) => end.parse().ok()?,
+        None => start,
+    };
+    Some((start, end))
+}
+
+/// Breaks a line of code into lines no wider than `width`. The lines after the
+/// first start with `marker`, and their text is indented by `indentation`.
+pub fn wrap_code_line(
+    resources: &Resources,
+    line: Vec<Span>,
+    width: Mm,
+    marker: &str,
+    indentation: Mm,
+) -> Vec<Vec<Span>> {
+    let marker_style = line
+        .iter()
+        .find_map(|span| match span {
+            Span::Text { style, .. } => Some(style.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| [Class::Code].iter().into());
+
+    let mut lines = vec![Vec::new()];
+    let mut x = Mm(0.0);
+    // Whether nothing but the continuation marker is on the current line
+    let mut fresh = true;
+
+    let mut spans = line;
+    spans.reverse();
+    while let Some(span) = spans.pop() {
+        let span_width = span.width(resources);
+        let current = lines.last_mut().expect("lines is never empty");
+        if x + span_width <= width {
+            x += span_width;
+            current.push(span);
+            fresh = false;
+            continue;
+        }
+        // Spaces at the end of a line aren't seen
+        if span.is_space() {
+            continue;
+        }
+
+        match span {
+            Span::Text { text, style } => {
+                let split = overlong_split(resources, &style, &text, width - x);
+                let start_width: Mm = width_of_text(resources, &style, &text[..split]).into();
+                let start_fits = fresh || x + start_width <= width;
+                if start_fits && (fresh || span_width > width - indentation) {
+                    // The word doesn't fit on a line of its own, so as much of
+                    // it is kept on this line as fits
+                    current.push(Span::text(text[..split].into(), style.clone()));
+                    if split < text.len() {
+                        spans.push(Span::text(text[split..].into(), style));
+                    }
+                } else {
+                    spans.push(Span::text(text, style));
+                }
+            }
+            span if fresh => {
+                current.push(span);
+                continue;
+            }
+            span => spans.push(span),
+        }
+        if spans.is_empty() {
+            break;
+        }
+        let current = lines.last_mut().expect("lines is never empty");
+        while current.last().map(Span::is_space).unwrap_or(false) {
+            current.pop();
+        }
+
+        let marker = Span::text(marker.into(), marker_style.clone());
+        let gap = indentation - marker.width(resources);
+        let mut continuation = vec![marker];
+        if gap.0 > 0.0 {
+            continuation.push(Span::gap(gap));
+        }
+        x = line_width(resources, &continuation);
+        fresh = true;
+        lines.push(continuation);
+    }
+    lines
+}
+
+/// Makes the font of a code block smaller, so that its longest line fits into
+/// `width`. The font is shrunk to whole point sizes, and never below
+/// `min_scale` of its original size.
+pub fn shrink_code_block(resources: &Resources, lines: &mut [Vec<Span>], width: Mm, min_scale: f64) {
+    let longest = lines
+        .iter()
+        .map(|line| line_width(resources, line))
+        .fold(Mm(0.0), |longest, line| if line > longest { line } else { longest });
+    if longest <= width {
+        return;
+    }
+
+    let cfg = resources.get_config();
+    let code_style: Style = [Class::Code].iter().into();
+    let size = f64::from(scale_from_style(cfg, &code_style).y);
+    let shrunk = (size * width.0 / longest.0)
+        .floor()
+        .max((size * min_scale).ceil());
+    if shrunk >= size {
+        return;
+    }
+
+    for span in lines.iter_mut().flat_map(|line| line.iter_mut()) {
+        if let Span::Text { ref mut style, .. } = span {
+            style.insert(Class::FontSize(shrunk as u8));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options() {
+        let info = CodeBlockInfo::parse("rust {linenos, hl=3-5,8, start=10}");
+        assert_eq!(info.language.as_deref(), Some("rust"));
+        assert!(info.line_numbers);
+        assert_eq!(info.highlighted, vec![(3, 5), (8, 8)]);
+        assert_eq!(info.first_line, Some(10));
+        assert_eq!(info.include, None);
+    }
+
+    #[test]
+    fn parses_quoted_options() {
+        let info = CodeBlockInfo::parse("python hl_lines=\"2 4-5\" linenos=false");
+        assert_eq!(info.language.as_deref(), Some("python"));
+        assert!(!info.line_numbers);
+        assert_eq!(info.highlighted, vec![(2, 2), (4, 5)]);
+    }
+
+    #[test]
+    fn parses_options_without_a_language() {
+        let info = CodeBlockInfo::parse("{linenos}");
+        assert_eq!(info.language, None);
+        assert!(info.line_numbers);
+
+        let info = CodeBlockInfo::parse("file=src/lib.rs lines=10-40");
+        assert_eq!(info.language, None);
+        assert_eq!(
+            info.include,
+            Some(Include {
+                path: "src/lib.rs".into(),
+                lines: (Some(10), Some(40)),
+                anchor: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_included_anchors() {
+        let info = CodeBlockInfo::parse("rust file=src/lib.rs anchor=main");
+        assert_eq!(info.language.as_deref(), Some("rust"));
+        assert_eq!(
+            info.include,
+            Some(Include {
+                path: "src/lib.rs".into(),
+                lines: (None, None),
+                anchor: Some("main".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_broken_and_unknown_options() {
+        let info = CodeBlockInfo::parse("rust hl=x start=y title=main.rs");
+        assert_eq!(info.language.as_deref(), Some("rust"));
+        assert!(info.highlighted.is_empty());
+        assert_eq!(info.first_line, None);
+    }
+
+    #[test]
+    fn highlights_lines_in_ranges() {
+        let info = CodeBlockInfo::parse("rust hl=3-5,8");
+        let highlighted: Vec<usize> = (1..10).filter(|&n| info.is_highlighted(n)).collect();
+        assert_eq!(highlighted, vec![3, 4, 5, 8]);
+    }
+}
@@ -0,0 +1,481 @@
+use failure::Error;
+use lopdf::{Dictionary, Object, Stream};
+use printpdf::{Mm, Pt, PdfDocumentReference};
+use crate::heading::HeadingPosition;
+use crate::resources::Resources;
+use crate::span::{PositionedSpan, Span};
+use crate::util::font_ascent_descent;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+
+/// A rendered PDF document.
+///
+/// `printpdf` has no support for annotations, named destinations, outlines or
+/// forms, so the document is saved once by `printpdf` and then amended with
+/// `lopdf` before it is written out.
+pub struct Document {
+    pdf: PdfDocumentReference,
+    links: Vec<Link>,
+    checkboxes: Vec<Checkbox>,
+    headings: Vec<HeadingPosition>,
+    /// The deepest heading level that is put into the outline
+    outline_depth: u8,
+}
+
+/// A clickable area on a page
+#[derive(Clone, Debug)]
+pub struct Link {
+    /// The index of the page the link is on
+    pub page: usize,
+    /// The lower left corner of the clickable area
+    pub lower_left: (Mm, Mm),
+    /// The upper right corner of the clickable area
+    pub upper_right: (Mm, Mm),
+    /// Where the link goes. Links starting with `#` go to a heading inside of
+    /// the document.
+    pub uri: String,
+}
+
+/// A checkbox form field that can be ticked in a PDF viewer
+#[derive(Clone, Debug)]
+pub struct Checkbox {
+    /// The index of the page the checkbox is on
+    pub page: usize,
+    /// The lower left corner of the checkbox
+    pub pos: (Mm, Mm),
+    /// The width and height of the checkbox
+    pub size: Mm,
+    pub checked: bool,
+}
+
+impl Document {
+    pub fn new(
+        pdf: PdfDocumentReference,
+        links: Vec<Link>,
+        checkboxes: Vec<Checkbox>,
+        headings: Vec<HeadingPosition>,
+        outline_depth: u8,
+    ) -> Self {
+        Self {
+            pdf,
+            links,
+            checkboxes,
+            headings,
+            outline_depth,
+        }
+    }
+
+    /// Save the PDF document, writing the contents to the target
+    pub fn save<W: Write>(self, target: &mut BufWriter<W>) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        self.pdf
+            .save(&mut BufWriter::new(&mut bytes))
+            .map_err(|_e| format_err!("Failed to save pdf file"))?;
+
+        if self.links.is_empty() && self.checkboxes.is_empty() && self.headings.is_empty() {
+            target.write_all(&bytes)?;
+            return Ok(());
+        }
+
+        let mut doc = lopdf::Document::load_from(&bytes[..])?;
+        let page_ids = doc.get_pages();
+
+        // Every heading can be linked to by its anchor, from inside of the
+        // document as well as from other documents
+        let mut destinations = Dictionary::new();
+        for position in self.headings.iter() {
+            if let Some(page_id) = page_ids.get(&(position.page as u32 + 1)) {
+                destinations.set(
+                    position.heading.anchor.clone(),
+                    destination(*page_id, position.y),
+                );
+            }
+        }
+        let destinations_id = doc.add_object(destinations);
+        catalog_mut(&mut doc)?.set("Dests", destinations_id);
+
+        let outline_depth = self.outline_depth;
+        let outline: Vec<&HeadingPosition> = self
+            .headings
+            .iter()
+            .filter(|p| p.heading.level <= outline_depth)
+            .collect();
+        if !outline.is_empty() {
+            let outline_id = add_outline(&mut doc, &page_ids, &outline);
+            let catalog = catalog_mut(&mut doc)?;
+            catalog.set("Outlines", outline_id);
+            catalog.set("PageMode", "UseOutlines");
+        }
+
+        for link in self.links {
+            let page_id = match page_ids.get(&(link.page as u32 + 1)) {
+                Some(id) => *id,
+                None => {
+                    warn!("Link points to a page that doesn't exist: {:?}", link);
+                    continue;
+                }
+            };
+
+            let action: Dictionary = if link.uri.starts_with('#') {
+                let anchor = &link.uri[1..];
+                if !self.headings.iter().any(|p| p.heading.anchor == anchor) {
+                    warn!("Link points to a heading that doesn't exist: {}", link.uri);
+                    continue;
+                }
+                vec![
+                    ("S", Object::from("GoTo")),
+                    ("D", Object::Name(anchor.as_bytes().to_vec())),
+                ]
+                .into_iter()
+                .collect()
+            } else {
+                vec![
+                    ("S", Object::from("URI")),
+                    ("URI", Object::string_literal(link.uri.clone())),
+                ]
+                .into_iter()
+                .collect()
+            };
+
+            let (x1, y1): (Pt, Pt) = (link.lower_left.0.into(), link.lower_left.1.into());
+            let (x2, y2): (Pt, Pt) = (link.upper_right.0.into(), link.upper_right.1.into());
+            let annotation: Dictionary = vec![
+                ("Type", Object::from("Annot")),
+                ("Subtype", Object::from("Link")),
+                ("Rect", vec![x1.into(), y1.into(), x2.into(), y2.into()].into()),
+                ("Border", vec![0.into(), 0.into(), 0.into()].into()),
+                ("A", action.into()),
+            ]
+            .into_iter()
+            .collect();
+            let annotation_id = doc.add_object(annotation);
+            add_annotation(&mut doc, page_id, annotation_id)?;
+        }
+
+        if !self.checkboxes.is_empty() {
+            add_checkboxes(&mut doc, &page_ids, &self.checkboxes)?;
+        }
+
+        doc.save_to(target)?;
+        Ok(())
+    }
+}
+
+/// A destination that shows the page scrolled down to `y`, without changing
+/// the horizontal position or the zoom
+fn destination(page_id: lopdf::ObjectId, y: Mm) -> Object {
+    let y: Pt = y.into();
+    vec![
+        page_id.into(),
+        Object::from("XYZ"),
+        Object::Null,
+        y.into(),
+        Object::Null,
+    ]
+    .into()
+}
+
+/// Adds a document outline, where every heading is nested inside of the closest
+/// heading before it that has a lower level. Returns the id of the outline's
+/// root.
+fn add_outline(
+    doc: &mut lopdf::Document,
+    page_ids: &BTreeMap<u32, lopdf::ObjectId>,
+    headings: &[&HeadingPosition],
+) -> lopdf::ObjectId {
+    let root_id = doc.new_object_id();
+    let item_ids: Vec<lopdf::ObjectId> = headings.iter().map(|_| doc.new_object_id()).collect();
+
+    // The parent of every item, or `None` for items at the top of the outline
+    let mut parents: Vec<Option<usize>> = Vec::with_capacity(headings.len());
+    let mut stack: Vec<usize> = Vec::new();
+    for (idx, position) in headings.iter().enumerate() {
+        while stack
+            .last()
+            .map(|&parent| headings[parent].heading.level >= position.heading.level)
+            .unwrap_or(false)
+        {
+            stack.pop();
+        }
+        parents.push(stack.last().cloned());
+        stack.push(idx);
+    }
+
+    let children = |parent: Option<usize>| -> Vec<usize> {
+        (0..headings.len())
+            .filter(|&idx| parents[idx] == parent)
+            .collect()
+    };
+    // Every item is open, so the count includes all of the descendants
+    let descendants = |item: usize| -> usize {
+        (item + 1..headings.len())
+            .take_while(|&idx| headings[idx].heading.level > headings[item].heading.level)
+            .count()
+    };
+
+    for (idx, position) in headings.iter().enumerate() {
+        let mut item = Dictionary::new();
+        item.set("Title", text_string(&position.heading.text));
+        item.set(
+            "Parent",
+            parents[idx].map(|p| item_ids[p]).unwrap_or(root_id),
+        );
+        let siblings = children(parents[idx]);
+        let sibling_idx = siblings.iter().position(|&s| s == idx).unwrap_or(0);
+        if sibling_idx > 0 {
+            item.set("Prev", item_ids[siblings[sibling_idx - 1]]);
+        }
+        if let Some(&next) = siblings.get(sibling_idx + 1) {
+            item.set("Next", item_ids[next]);
+        }
+        let own_children = children(Some(idx));
+        if let (Some(&first), Some(&last)) = (own_children.first(), own_children.last()) {
+            item.set("First", item_ids[first]);
+            item.set("Last", item_ids[last]);
+            item.set("Count", descendants(idx) as i64);
+        }
+        if let Some(page_id) = page_ids.get(&(position.page as u32 + 1)) {
+            item.set("Dest", destination(*page_id, position.y));
+        }
+        doc.objects.insert(item_ids[idx], Object::Dictionary(item));
+    }
+
+    let top_level = children(None);
+    let mut root = Dictionary::new();
+    root.set("Type", "Outlines");
+    if let (Some(&first), Some(&last)) = (top_level.first(), top_level.last()) {
+        root.set("First", item_ids[first]);
+        root.set("Last", item_ids[last]);
+    }
+    root.set("Count", headings.len() as i64);
+    doc.objects.insert(root_id, Object::Dictionary(root));
+    root_id
+}
+
+/// Encodes text for use outside of a page's content, like in the outline. Text
+/// that isn't ASCII is encoded as UTF-16 with a byte order mark.
+fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        Object::string_literal(text)
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in text.encode_utf16() {
+            bytes.push((unit >> 8) as u8);
+            bytes.push(unit as u8);
+        }
+        Object::String(bytes, lopdf::StringFormat::Hexadecimal)
+    }
+}
+
+fn add_annotation(
+    doc: &mut lopdf::Document,
+    page_id: lopdf::ObjectId,
+    annotation_id: lopdf::ObjectId,
+) -> Result<(), Error> {
+    let page = doc
+        .get_object_mut(page_id)
+        .and_then(|obj| obj.as_dict_mut())
+        .ok_or_else(|| format_err!("Page {} is not a dictionary", page_id.0))?;
+    if !page.has("Annots") {
+        page.set("Annots", Vec::<Object>::new());
+    }
+    if let Some(annotations) = page.get_mut("Annots").and_then(|obj| obj.as_array_mut()) {
+        annotations.push(annotation_id.into());
+    }
+    Ok(())
+}
+
+/// Adds an interactive form with a checkbox field for every checkbox. The
+/// fields draw themselves, with a tick from the ZapfDingbats font when they
+/// are checked.
+fn add_checkboxes(
+    doc: &mut lopdf::Document,
+    page_ids: &BTreeMap<u32, lopdf::ObjectId>,
+    checkboxes: &[Checkbox],
+) -> Result<(), Error> {
+    let font: Dictionary = vec![
+        ("Type", Object::from("Font")),
+        ("Subtype", Object::from("Type1")),
+        ("BaseFont", Object::from("ZapfDingbats")),
+    ]
+    .into_iter()
+    .collect();
+    let font_id = doc.add_object(font);
+    let mut fonts = Dictionary::new();
+    fonts.set("ZaDb", font_id);
+    let mut resources = Dictionary::new();
+    resources.set("Font", fonts);
+
+    let mut fields = Vec::new();
+    for (idx, checkbox) in checkboxes.iter().enumerate() {
+        let page_id = match page_ids.get(&(checkbox.page as u32 + 1)) {
+            Some(id) => *id,
+            None => {
+                warn!("Checkbox is on a page that doesn't exist: {:?}", checkbox);
+                continue;
+            }
+        };
+
+        let size: Pt = checkbox.size.into();
+        let (x, y): (Pt, Pt) = (checkbox.pos.0.into(), checkbox.pos.1.into());
+        let border = format!(
+            "0 g 0.5 w 0.25 0.25 {size} {size} re S\n",
+            size = size.0 - 0.5
+        );
+        let tick = format!(
+            "BT /ZaDb {} Tf {} {} Td (4) Tj ET\n",
+            size.0 * 0.8,
+            size.0 * 0.12,
+            size.0 * 0.2
+        );
+        let appearance = |content: String| {
+            let dict: Dictionary = vec![
+                ("Type", Object::from("XObject")),
+                ("Subtype", Object::from("Form")),
+                ("BBox", vec![0.into(), 0.into(), size.into(), size.into()].into()),
+                ("Resources", resources.clone().into()),
+            ]
+            .into_iter()
+            .collect();
+            Stream::new(dict, content.into_bytes())
+        };
+        let on_id = doc.add_object(appearance(border.clone() + &tick));
+        let off_id = doc.add_object(appearance(border));
+        let mut normal = Dictionary::new();
+        normal.set("Yes", on_id);
+        normal.set("Off", off_id);
+        let mut appearances = Dictionary::new();
+        appearances.set("N", normal);
+
+        let state = if checkbox.checked { "Yes" } else { "Off" };
+        let field: Dictionary = vec![
+            ("Type", Object::from("Annot")),
+            ("Subtype", Object::from("Widget")),
+            ("FT", Object::from("Btn")),
+            ("T", Object::string_literal(format!("task{}", idx + 1))),
+            ("Rect", vec![x.into(), y.into(), (x + size).into(), (y + size).into()].into()),
+            ("F", 4.into()),
+            ("P", page_id.into()),
+            ("V", state.into()),
+            ("AS", state.into()),
+            ("AP", appearances.into()),
+            ("DA", Object::string_literal("/ZaDb 0 Tf 0 g")),
+        ]
+        .into_iter()
+        .collect();
+        let field_id = doc.add_object(field);
+        add_annotation(doc, page_id, field_id)?;
+        fields.push(Object::from(field_id));
+    }
+
+    let form: Dictionary = vec![
+        ("Fields", Object::from(fields)),
+        ("DR", resources.into()),
+        ("DA", Object::string_literal("/ZaDb 0 Tf 0 g")),
+    ]
+    .into_iter()
+    .collect();
+    let form_id = doc.add_object(form);
+    catalog_mut(doc)?.set("AcroForm", form_id);
+    Ok(())
+}
+
+fn catalog_mut(doc: &mut lopdf::Document) -> Result<&mut Dictionary, Error> {
+    let catalog_id = doc
+        .trailer
+        .get("Root")
+        .and_then(|obj| obj.as_reference())
+        .ok_or_else(|| format_err!("PDF has no catalog"))?;
+    doc.get_object_mut(catalog_id)
+        .and_then(|obj| obj.as_dict_mut())
+        .ok_or_else(|| format_err!("PDF catalog is not a dictionary"))
+}
+
+/// Finds the areas covered by link text and linked images on a page. Words of
+/// the same link that are next to each other on a line are joined into one
+/// area, so a link that wraps onto the next line gets one area per line.
+pub fn find_links(resources: &Resources, page: usize, spans: &[PositionedSpan]) -> Vec<Link> {
+    let mut links = Vec::new();
+    // The link that is currently being extended, and the baseline it is on
+    let mut current: Option<(Mm, Link)> = None;
+
+    for positioned in spans {
+        let (x, y) = positioned.pos;
+        let (uri, lower_left, upper_right) = match positioned.span {
+            Span::Text {
+                ref text,
+                ref style,
+            } => match style.link() {
+                Some(uri) => {
+                    let (ascent, descent) = font_ascent_descent(resources, style);
+                    let width = positioned.span.width(resources);
+                    (uri, (x, y + descent.into()), (x + width, y + ascent.into()))
+                }
+                None => {
+                    if !text.trim().is_empty() {
+                        links.extend(current.take().map(|(_, link)| link));
+                    }
+                    continue;
+                }
+            },
+            // Images stand on the baseline
+            Span::Image {
+                width,
+                height,
+                link: Some(ref uri),
+                ..
+            } => (uri.as_str(), (x, y), (x + width, y + height)),
+            // Gaps are the stretched space between words
+            Span::Gap { .. } => continue,
+            _ => {
+                links.extend(current.take().map(|(_, link)| link));
+                continue;
+            }
+        };
+
+        if let Some((baseline, ref mut link)) = current {
+            if link.uri == uri && baseline == y {
+                if lower_left.1 < link.lower_left.1 {
+                    link.lower_left.1 = lower_left.1;
+                }
+                if upper_right.1 > link.upper_right.1 {
+                    link.upper_right.1 = upper_right.1;
+                }
+                link.upper_right.0 = upper_right.0;
+                continue;
+            }
+        }
+        links.extend(current.take().map(|(_, link)| link));
+        current = Some((
+            y,
+            Link {
+                page,
+                lower_left,
+                upper_right,
+                uri: uri.to_string(),
+            },
+        ));
+    }
+    links.extend(current.map(|(_, link)| link));
+    links
+}
+
+/// Finds the markers of task list items on a page, so that they can be
+/// replaced by checkboxes
+pub fn find_checkboxes(resources: &Resources, page: usize, spans: &[PositionedSpan]) -> Vec<Checkbox> {
+    spans
+        .iter()
+        .filter_map(|positioned| {
+            let checked = match positioned.span {
+                Span::Text { ref style, .. } => style.checkbox()?,
+                _ => return None,
+            };
+            Some(Checkbox {
+                page,
+                pos: positioned.pos,
+                size: positioned.span.width(resources),
+                checked,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,57 @@
+use printpdf::Mm;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub struct Heading {
+    pub level: u8,
+    /// The plain text of the heading, without any formatting
+    pub text: String,
+    /// The unique name that links use to refer to the heading
+    pub anchor: String,
+}
+
+/// Where a heading ended up after the document was split into pages
+#[derive(Clone, Debug)]
+pub struct HeadingPosition {
+    pub heading: Heading,
+    /// The index of the page the heading is on
+    pub page: usize,
+    /// The top of the heading's first line
+    pub y: Mm,
+}
+
+/// Generates anchors the same way GitHub does, so that links written for
+/// GitHub's markdown renderer also work in the PDF.
+#[derive(Default)]
+pub struct Anchors {
+    used: HashMap<String, usize>,
+}
+
+impl Anchors {
+    /// Turns `text` into an anchor, adding a number to the end of it if the
+    /// same anchor was already handed out.
+    pub fn anchor(&mut self, text: &str) -> String {
+        let slug = slugify(text);
+        let count = self.used.entry(slug.clone()).or_insert(0);
+        let anchor = match *count {
+            0 => slug,
+            n => format!("{}-{}", slug, n),
+        };
+        *count += 1;
+        anchor
+    }
+}
+
+/// Lowercases the text, removes punctuation and replaces spaces with dashes
+pub fn slugify(text: &str) -> String {
+    text.trim()
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,530 @@
+use failure::Error;
+use printpdf::{Color, Greyscale, Rgb};
+use std::str::FromStr;
+
+/// The kinds of tokens of source code that are set in a color of their own
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum Token {
+    Keyword,
+    /// Constants that have a name, like `true`, `false` and `null`
+    Literal,
+    String,
+    Number,
+    Comment,
+    /// The keys of JSON objects, YAML mappings and TOML tables, along with the
+    /// headers of TOML tables
+    Key,
+}
+
+/// The colors that highlighted source code is set in
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTheme {
+    pub keyword: Color,
+    pub literal: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub key: Color,
+}
+
+impl SyntaxTheme {
+    /// Dark colors that stand out on a white or light grey background
+    pub fn light() -> Self {
+        Self {
+            keyword: rgb(0xd7, 0x3a, 0x49),
+            literal: rgb(0x00, 0x5c, 0xc5),
+            string: rgb(0x03, 0x2f, 0x62),
+            number: rgb(0x00, 0x5c, 0xc5),
+            comment: rgb(0x6a, 0x73, 0x7d),
+            key: rgb(0x6f, 0x42, 0xc1),
+        }
+    }
+
+    /// The accent colors of Ethan Schoonover's Solarized palette
+    pub fn solarized() -> Self {
+        Self {
+            keyword: rgb(0x85, 0x99, 0x00),
+            literal: rgb(0xcb, 0x4b, 0x16),
+            string: rgb(0x2a, 0xa1, 0x98),
+            number: rgb(0xd3, 0x36, 0x82),
+            comment: rgb(0x93, 0xa1, 0xa1),
+            key: rgb(0x26, 0x8b, 0xd2),
+        }
+    }
+
+    /// Shades of grey, for documents that are printed in black and white
+    pub fn greyscale() -> Self {
+        Self {
+            keyword: grey(0.0),
+            literal: grey(0.25),
+            string: grey(0.35),
+            number: grey(0.25),
+            comment: grey(0.55),
+            key: grey(0.2),
+        }
+    }
+
+    pub fn color(&self, token: Token) -> &Color {
+        match token {
+            Token::Keyword => &self.keyword,
+            Token::Literal => &self.literal,
+            Token::String => &self.string,
+            Token::Number => &self.number,
+            Token::Comment => &self.comment,
+            Token::Key => &self.key,
+        }
+    }
+}
+
+impl FromStr for SyntaxTheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "light" => Ok(SyntaxTheme::light()),
+            "solarized" => Ok(SyntaxTheme::solarized()),
+            "greyscale" | "grayscale" => Ok(SyntaxTheme::greyscale()),
+            _ => Err(format_err!(
+                "Unknown syntax theme {:?}, expected light, solarized or greyscale",
+                s
+            )),
+        }
+    }
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> Color {
+    let channel = |value: u8| f64::from(value) / 255.0;
+    Color::Rgb(Rgb::new(channel(r), channel(g), channel(b), None))
+}
+
+fn grey(value: f64) -> Color {
+    Color::Greyscale(Greyscale::new(value, None))
+}
+
+/// How the keys of a language's mappings are told apart from other text
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Keys {
+    None,
+    /// Strings that are followed by a `:`
+    Json,
+    /// Text at the start of a line that is followed by a `:`
+    Yaml,
+    /// Text at the start of a line that is followed by a `=`, and `[tables]`
+    Toml,
+}
+
+/// The tokens of a language that are highlighted
+struct Language {
+    /// The names of the language in the info strings of fenced code blocks
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Line comments only start at the start of a word, like in shell scripts
+    word_comments: bool,
+    quotes: &'static [char],
+    /// Strings may be surrounded by three quotes, and span several lines
+    triple_quotes: bool,
+    /// A `'` is only a quote if it is part of a character literal, otherwise
+    /// it starts a lifetime
+    lifetimes: bool,
+    /// A `#` at the start of a line starts a preprocessor directive
+    preprocessor: bool,
+    keys: Keys,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+            "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        word_comments: false,
+        quotes: &['"', '\''],
+        triple_quotes: false,
+        lifetimes: true,
+        preprocessor: false,
+        keys: Keys::None,
+    },
+    Language {
+        names: &["python", "py", "python3"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        line_comments: &["#"],
+        block_comment: None,
+        word_comments: false,
+        quotes: &['"', '\''],
+        triple_quotes: true,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::None,
+    },
+    Language {
+        names: &["sh", "bash", "shell", "zsh"],
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case",
+            "esac", "in", "function", "return", "local", "export", "select",
+        ],
+        literals: &[],
+        line_comments: &["#"],
+        block_comment: None,
+        word_comments: true,
+        quotes: &['"', '\''],
+        triple_quotes: false,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::None,
+    },
+    Language {
+        names: &["json"],
+        keywords: &[],
+        literals: &["true", "false", "null"],
+        line_comments: &[],
+        block_comment: None,
+        word_comments: false,
+        quotes: &['"'],
+        triple_quotes: false,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::Json,
+    },
+    Language {
+        names: &["yaml", "yml"],
+        keywords: &[],
+        literals: &["true", "false", "null", "yes", "no", "on", "off"],
+        line_comments: &["#"],
+        block_comment: None,
+        word_comments: true,
+        quotes: &['"', '\''],
+        triple_quotes: false,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::Yaml,
+    },
+    Language {
+        names: &["toml"],
+        keywords: &[],
+        literals: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        word_comments: false,
+        quotes: &['"', '\''],
+        triple_quotes: true,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::Toml,
+    },
+    Language {
+        names: &["c", "h"],
+        keywords: &[
+            "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+            "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long",
+            "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct",
+            "switch", "typedef", "union", "unsigned", "void", "volatile", "while",
+        ],
+        literals: &["NULL", "true", "false"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        word_comments: false,
+        quotes: &['"', '\''],
+        triple_quotes: false,
+        lifetimes: false,
+        preprocessor: true,
+        keys: Keys::None,
+    },
+    Language {
+        names: &["javascript", "js", "mjs", "cjs", "jsx"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger",
+            "default", "delete", "do", "else", "export", "extends", "finally", "for", "function",
+            "if", "import", "in", "instanceof", "let", "new", "of", "return", "static", "super",
+            "switch", "this", "throw", "try", "typeof", "var", "void", "while", "with", "yield",
+        ],
+        literals: &["true", "false", "null", "undefined", "NaN", "Infinity"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        word_comments: false,
+        quotes: &['"', '\'', '`'],
+        triple_quotes: false,
+        lifetimes: false,
+        preprocessor: false,
+        keys: Keys::None,
+    },
+];
+
+/// Splits `code` into tokens, if `language` is one of the languages that can
+/// be highlighted. Text that isn't highlighted has no token.
+pub fn highlight(language: &str, code: &str) -> Option<Vec<(String, Option<Token>)>> {
+    let language = language.to_lowercase();
+    let language = LANGUAGES
+        .iter()
+        .find(|candidate| candidate.names.contains(&language.as_str()))?;
+
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens: Vec<(String, Option<Token>)> = Vec::new();
+    let mut push = |text: &[char], token: Option<Token>| match tokens.last_mut() {
+        Some((last, last_token)) if *last_token == token => last.extend(text),
+        _ => tokens.push((text.iter().collect(), token)),
+    };
+
+    // Whether there is nothing but indentation in front of `i` on its line
+    let mut line_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        let c = rest[0];
+        let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+
+        if c.is_whitespace() || (language.keys == Keys::Yaml && line_start && c == '-') {
+            if c == '\n' {
+                line_start = true;
+            }
+            push(&rest[..1], None);
+            i += 1;
+            continue;
+        }
+        let at_line_start = line_start;
+        line_start = false;
+
+        let (len, token) = if let Some(len) = key_len(language.keys, rest, at_line_start) {
+            (len, Some(Token::Key))
+        } else if language.preprocessor && at_line_start && c == '#' {
+            (1 + word_len(&rest[1..]), Some(Token::Keyword))
+        } else if language.line_comments.iter().any(|start| starts_with(rest, start))
+            && !(language.word_comments && previous.map(|p| !p.is_whitespace()).unwrap_or(false))
+        {
+            (find(rest, 0, "\n").unwrap_or(rest.len()), Some(Token::Comment))
+        } else if let Some(len) = language
+            .block_comment
+            .filter(|(start, _)| starts_with(rest, start))
+            .map(|(start, end)| {
+                let start = start.chars().count();
+                find(rest, start, end).map_or(rest.len(), |idx| idx + end.chars().count())
+            })
+        {
+            (len, Some(Token::Comment))
+        } else if let Some(len) = string_len(language, rest) {
+            let after = rest[len..].iter().find(|c| !c.is_whitespace());
+            if language.keys == Keys::Json && after == Some(&':') {
+                (len, Some(Token::Key))
+            } else {
+                (len, Some(Token::String))
+            }
+        } else if c.is_ascii_digit() && !previous.map(is_word_char).unwrap_or(false) {
+            (number_len(rest), Some(Token::Number))
+        } else if is_word_char(c) {
+            let len = word_len(rest);
+            let word: String = rest[..len].iter().collect();
+            if language.keywords.contains(&word.as_str()) {
+                (len, Some(Token::Keyword))
+            } else if language.literals.contains(&word.as_str()) {
+                (len, Some(Token::Literal))
+            } else {
+                (len, None)
+            }
+        } else {
+            (1, None)
+        };
+        push(&rest[..len], token);
+        i += len;
+    }
+    Some(tokens)
+}
+
+/// The length of the key at the start of `text`, if there is one
+fn key_len(keys: Keys, text: &[char], at_line_start: bool) -> Option<usize> {
+    if !at_line_start {
+        return None;
+    }
+    let line = find(text, 0, "\n").unwrap_or(text.len());
+    let separator = match keys {
+        Keys::Yaml => ':',
+        Keys::Toml if text[0] == '[' => {
+            return find(&text[..line], 0, "]").map(|idx| idx + 1);
+        }
+        Keys::Toml => '=',
+        Keys::None | Keys::Json => return None,
+    };
+    let end = text[..line].iter().position(|&c| c == separator)?;
+    // YAML keys are followed by a space or the end of the line, so that
+    // values like URLs aren't taken for keys
+    if keys == Keys::Yaml && text.get(end + 1).map(|c| !c.is_whitespace()).unwrap_or(false) {
+        return None;
+    }
+    let key = &text[..end];
+    let is_key = key
+        .iter()
+        .all(|&c| is_word_char(c) || ['-', '.', '"', '\'', ' '].contains(&c));
+    let len = key.len() - key.iter().rev().take_while(|c| c.is_whitespace()).count();
+    if is_key && len > 0 {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// The length of the string or character literal at the start of `text`, if
+/// there is one
+fn string_len(language: &Language, text: &[char]) -> Option<usize> {
+    let quote = text[0];
+    if !language.quotes.contains(&quote) {
+        return None;
+    }
+    if language.triple_quotes && text.len() >= 3 && text[1] == quote && text[2] == quote {
+        let end: String = [quote; 3].iter().collect();
+        return Some(find(text, 3, &end).map_or(text.len(), |idx| idx + 3));
+    }
+    if language.lifetimes && quote == '\'' && text.get(1) != Some(&'\\') && text.get(2) != Some(&'\'') {
+        return None;
+    }
+    let mut idx = 1;
+    while idx < text.len() {
+        match text[idx] {
+            '\\' => idx += 2,
+            c if c == quote => return Some(idx + 1),
+            _ => idx += 1,
+        }
+    }
+    Some(text.len())
+}
+
+fn number_len(text: &[char]) -> usize {
+    let mut len = 0;
+    while len < text.len() {
+        let c = text[len];
+        let decimal_point = c == '.' && text.get(len + 1).map(char::is_ascii_digit).unwrap_or(false);
+        if !(c.is_alphanumeric() || c == '_' || decimal_point) {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+fn word_len(text: &[char]) -> usize {
+    text.iter().take_while(|&&c| is_word_char(c)).count()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn starts_with(text: &[char], start: &str) -> bool {
+    start
+        .chars()
+        .enumerate()
+        .all(|(idx, c)| text.get(idx) == Some(&c))
+}
+
+/// The index of the first `pattern` in `text`, from `from` on
+fn find(text: &[char], from: usize, pattern: &str) -> Option<usize> {
+    (from..text.len()).find(|&idx| starts_with(&text[idx..], pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The highlighted tokens of `code`, leaving out the text in between
+    fn tokens(language: &str, code: &str) -> Vec<(String, Token)> {
+        highlight(language, code)
+            .expect("language to be known")
+            .into_iter()
+            .filter_map(|(text, token)| token.map(|token| (text, token)))
+            .collect()
+    }
+
+    fn token(text: &str, token: Token) -> (String, Token) {
+        (text.to_string(), token)
+    }
+
+    #[test]
+    fn tells_rust_lifetimes_from_characters() {
+        assert_eq!(
+            tokens("rust", "fn f<'a>(x: &'a str) -> char { 'x' }"),
+            vec![token("fn", Token::Keyword), token("'x'", Token::String)]
+        );
+        assert_eq!(
+            tokens("rs", r"let c = '\n';"),
+            vec![token("let", Token::Keyword), token(r"'\n'", Token::String)]
+        );
+    }
+
+    #[test]
+    fn starts_shell_comments_at_words() {
+        assert_eq!(
+            tokens("sh", "echo ${#list} # count\nls"),
+            vec![token("# count", Token::Comment)]
+        );
+        assert_eq!(
+            tokens("bash", "if true; then\n  # nothing\nfi"),
+            vec![
+                token("if", Token::Keyword),
+                token("then", Token::Keyword),
+                token("# nothing", Token::Comment),
+                token("fi", Token::Keyword),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_yaml_keys_and_comments() {
+        assert_eq!(
+            tokens("yaml", "# config\nname: a#b\nurl: http://x\n- on # yes"),
+            vec![
+                token("# config", Token::Comment),
+                token("name", Token::Key),
+                token("url", Token::Key),
+                token("on", Token::Literal),
+                token("# yes", Token::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_triple_quoted_strings() {
+        assert_eq!(
+            tokens("python", "s = \"\"\"a \"quoted\"\nline\"\"\" # done"),
+            vec![
+                token("\"\"\"a \"quoted\"\nline\"\"\"", Token::String),
+                token("# done", Token::Comment),
+            ]
+        );
+        // The string runs to the end if it isn't closed
+        assert_eq!(
+            tokens("py", "'''open\nstring"),
+            vec![token("'''open\nstring", Token::String)]
+        );
+    }
+
+    #[test]
+    fn tells_json_keys_from_strings() {
+        assert_eq!(
+            tokens("json", "{\"key\" : \"value\", \"n\": [1.5, null]}"),
+            vec![
+                token("\"key\"", Token::Key),
+                token("\"value\"", Token::String),
+                token("\"n\"", Token::Key),
+                token("1.5", Token::Number),
+                token("null", Token::Literal),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_languages_alone() {
+        assert!(highlight("cobol", "DISPLAY 1.").is_none());
+    }
+}
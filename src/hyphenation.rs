@@ -0,0 +1,254 @@
+use failure::Error;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Marks a place where a word may be hyphenated, without showing up otherwise
+pub const SOFT_HYPHEN: char = '\u{AD}';
+
+const EN_US_PATTERNS: &str = include_str!("../assets/hyphenation/hyph-en-us.pat.txt");
+const EN_US_EXCEPTIONS: &str = include_str!("../assets/hyphenation/hyph-en-us.hyp.txt");
+
+/// Finds the places where words can be hyphenated, using TeX style hyphenation
+/// patterns (Frank M. Liang's algorithm)
+#[derive(Clone)]
+pub struct Hyphenator {
+    /// The values between the letters of every pattern, by the letters of the
+    /// pattern. Odd values allow a hyphen, even values forbid it.
+    patterns: HashMap<String, Vec<u8>>,
+    /// The letters of the longest pattern
+    max_pattern_len: usize,
+    /// Words that aren't hyphenated by the patterns, along with the places their
+    /// hyphens go
+    exceptions: HashMap<String, Vec<usize>>,
+    /// The fewest letters that are kept in front of a hyphen
+    pub left_min: usize,
+    /// The fewest letters that are carried over to the next line
+    pub right_min: usize,
+}
+
+impl Hyphenator {
+    /// Reads patterns like `.ach4` or `a5bal`, and exceptions like `ta-ble`,
+    /// separated by whitespace. Everything after a `%` on a line is a comment.
+    pub fn new(patterns: &str, exceptions: &str) -> Result<Self, Error> {
+        let mut hyphenator = Self {
+            patterns: HashMap::new(),
+            max_pattern_len: 0,
+            exceptions: HashMap::new(),
+            left_min: 2,
+            right_min: 3,
+        };
+
+        for pattern in words(patterns) {
+            let mut letters = String::new();
+            let mut values = vec![0];
+            for c in pattern.chars() {
+                match c.to_digit(10) {
+                    Some(value) => *values.last_mut().expect("values is never empty") = value as u8,
+                    None => {
+                        letters.push(c);
+                        values.push(0);
+                    }
+                }
+            }
+            if letters.is_empty() {
+                return Err(format_err!("Hyphenation pattern {:?} has no letters", pattern));
+            }
+            hyphenator.max_pattern_len = hyphenator.max_pattern_len.max(values.len() - 1);
+            hyphenator.patterns.insert(letters, values);
+        }
+
+        for exception in words(exceptions) {
+            let mut positions = Vec::new();
+            let mut letters = 0;
+            for c in exception.chars() {
+                if c == '-' {
+                    positions.push(letters);
+                } else {
+                    letters += 1;
+                }
+            }
+            hyphenator
+                .exceptions
+                .insert(exception.replace('-', "").to_lowercase(), positions);
+        }
+
+        Ok(hyphenator)
+    }
+
+    /// The patterns for one of the languages that are built in. Only US English
+    /// (`en-us`) is built in at the moment; other languages can be loaded with
+    /// `Hyphenator::new`.
+    pub fn language(language: &str) -> Result<Self, Error> {
+        match language.to_lowercase().as_str() {
+            "en" | "en-us" => Self::new(EN_US_PATTERNS, EN_US_EXCEPTIONS),
+            _ => Err(format_err!("No hyphenation patterns for language {:?}", language)),
+        }
+    }
+
+    /// The indices of the characters of `word` that a hyphen may be put in front
+    /// of. Words with anything but letters in them are never hyphenated.
+    pub fn hyphenate(&self, word: &str) -> Vec<usize> {
+        let letters: Vec<char> = word
+            .chars()
+            .map(|c| c.to_lowercase().next().unwrap_or(c))
+            .collect();
+        let len = letters.len();
+        if len < self.left_min + self.right_min || !letters.iter().all(|c| c.is_alphabetic()) {
+            return Vec::new();
+        }
+        let allowed = |&position: &usize| position >= self.left_min && len - position >= self.right_min;
+
+        let lowercase: String = letters.iter().collect();
+        if let Some(positions) = self.exceptions.get(&lowercase) {
+            return positions.iter().cloned().filter(allowed).collect();
+        }
+
+        // The word, with dots marking its start and end. `values[i]` is the
+        // value of the place in front of `dotted[i]`.
+        let dotted: Vec<char> = Some('.')
+            .into_iter()
+            .chain(letters.iter().cloned())
+            .chain(Some('.'))
+            .collect();
+        let mut values = vec![0; dotted.len() + 1];
+        for start in 0..dotted.len() {
+            let mut pattern = String::new();
+            for &c in dotted[start..].iter().take(self.max_pattern_len) {
+                pattern.push(c);
+                if let Some(pattern_values) = self.patterns.get(&pattern) {
+                    for (offset, &value) in pattern_values.iter().enumerate() {
+                        let place = &mut values[start + offset];
+                        *place = (*place).max(value);
+                    }
+                }
+            }
+        }
+
+        // A hyphen in front of letter `i` of the word is in front of `i + 1`
+        // of the dotted word
+        (1..len)
+            .filter(allowed)
+            .filter(|&position| values[position + 1] % 2 == 1)
+            .collect()
+    }
+}
+
+impl fmt::Debug for Hyphenator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hyphenator")
+            .field("patterns", &self.patterns.len())
+            .field("exceptions", &self.exceptions.len())
+            .field("left_min", &self.left_min)
+            .field("right_min", &self.right_min)
+            .finish()
+    }
+}
+
+/// Splits `text` into the pieces between which a hyphen may be put. Soft hyphens
+/// in the text mark these places themselves, and are left out of the pieces.
+/// Otherwise the letters of the text are hyphenated with `hyphenator`, keeping
+/// any punctuation around them attached.
+pub fn syllables(hyphenator: Option<&Hyphenator>, text: &str) -> Vec<String> {
+    if text.contains(SOFT_HYPHEN) {
+        return text
+            .split(SOFT_HYPHEN)
+            .filter(|piece| !piece.is_empty())
+            .map(String::from)
+            .collect();
+    }
+    let hyphenator = match hyphenator {
+        Some(hyphenator) => hyphenator,
+        None => return vec![text.to_string()],
+    };
+
+    let start = text.find(char::is_alphabetic).unwrap_or(text.len());
+    let end = text
+        .rfind(char::is_alphabetic)
+        .map(|idx| idx + text[idx..].chars().next().map_or(0, char::len_utf8))
+        .unwrap_or(start);
+    let word = &text[start..end];
+
+    let mut syllables = Vec::new();
+    let mut piece_start = 0;
+    let boundaries: Vec<usize> = word.char_indices().map(|(idx, _)| idx).collect();
+    for position in hyphenator.hyphenate(word) {
+        let boundary = start + boundaries[position];
+        syllables.push(text[piece_start..boundary].to_string());
+        piece_start = boundary;
+    }
+    syllables.push(text[piece_start..].to_string());
+    syllables
+}
+
+/// The whitespace separated words of a pattern or exception list, without
+/// comments
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.split('%').next().unwrap_or(""))
+        .flat_map(str::split_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hyphenated(hyphenator: &Hyphenator, word: &str) -> String {
+        let mut result = String::new();
+        let positions = hyphenator.hyphenate(word);
+        for (idx, c) in word.chars().enumerate() {
+            if positions.contains(&idx) {
+                result.push('-');
+            }
+            result.push(c);
+        }
+        result
+    }
+
+    #[test]
+    fn hyphenates_with_the_patterns() {
+        let hyphenator = Hyphenator::language("en-us").unwrap();
+        assert_eq!(hyphenated(&hyphenator, "hyphenation"), "hy-phen-ation");
+        assert_eq!(hyphenated(&hyphenator, "Hyphenation"), "Hy-phen-ation");
+        assert_eq!(hyphenated(&hyphenator, "computer"), "com-puter");
+        assert_eq!(hyphenated(&hyphenator, "algorithm"), "al-go-rithm");
+    }
+
+    #[test]
+    fn keeps_short_words_and_words_with_other_characters_whole() {
+        let hyphenator = Hyphenator::language("en-us").unwrap();
+        assert_eq!(hyphenated(&hyphenator, "the"), "the");
+        assert_eq!(hyphenated(&hyphenator, "hyphen2tion"), "hyphen2tion");
+    }
+
+    #[test]
+    fn uses_the_exceptions() {
+        let hyphenator = Hyphenator::language("en-us").unwrap();
+        assert_eq!(hyphenated(&hyphenator, "associate"), "as-so-ciate");
+        assert_eq!(hyphenated(&hyphenator, "Table"), "Ta-ble");
+        assert_eq!(hyphenated(&hyphenator, "present"), "present");
+    }
+
+    #[test]
+    fn splits_words_into_syllables() {
+        let hyphenator = Hyphenator::language("en-us").unwrap();
+        assert_eq!(
+            syllables(Some(&hyphenator), "(hyphenation),"),
+            vec!["(hy", "phen", "ation),"]
+        );
+        assert_eq!(syllables(None, "hyphenation"), vec!["hyphenation"]);
+    }
+
+    #[test]
+    fn splits_at_soft_hyphens() {
+        let hyphenator = Hyphenator::language("en-us").unwrap();
+        let text = "hyph\u{AD}enation\u{AD}";
+        assert_eq!(syllables(None, text), vec!["hyph", "enation"]);
+        // Soft hyphens take the place of the patterns
+        assert_eq!(syllables(Some(&hyphenator), text), vec!["hyph", "enation"]);
+    }
+
+    #[test]
+    fn rejects_patterns_without_letters() {
+        assert!(Hyphenator::new("a1b 42", "").is_err());
+    }
+}
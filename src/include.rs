@@ -0,0 +1,221 @@
+use failure::Error;
+
+/// A part of a file that is included into a code block, either with
+/// `file=src/lib.rs lines=10-40` in its info string, or with a
+/// `{{#include src/lib.rs:10:40}}` line in its text
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Include {
+    /// The path of the file, relative to `Config::resources_directory`. Files
+    /// outside of that directory can't be included.
+    pub path: String,
+    /// The first and last line of the file that are included, counting from
+    /// 1. `None` stands for the start or the end of the file.
+    pub lines: (Option<usize>, Option<usize>),
+    /// The name of the region between an `ANCHOR: name` and an
+    /// `ANCHOR_END: name` comment that is included
+    pub anchor: Option<String>,
+}
+
+impl Include {
+    /// Reads an mdBook style `{{#include path}}` directive. The path can be
+    /// followed by `:10:40`, `:10:`, `::40` or `:10` to include a range of
+    /// lines, or by `:name` to include an anchored region.
+    pub fn parse_directive(line: &str) -> Option<Self> {
+        let args = line
+            .trim()
+            .strip_prefix("{{#include")?
+            .strip_suffix("}}")?
+            .trim();
+        if args.is_empty() {
+            return None;
+        }
+
+        let mut parts = args.splitn(3, ':');
+        let mut include = Include {
+            path: parts.next()?.to_string(),
+            ..Default::default()
+        };
+        match (parts.next(), parts.next()) {
+            (None, _) => {}
+            (Some(first), None) if !first.is_empty() => match first.parse() {
+                Ok(line) => include.lines = (Some(line), Some(line)),
+                Err(_) => include.anchor = Some(first.to_string()),
+            },
+            (Some(first), last) => {
+                include.lines = (parse_line(first)?, parse_line(last.unwrap_or(""))?);
+            }
+        }
+        Some(include)
+    }
+
+    /// Picks the included lines out of the `text` of the file. Returns them,
+    /// and the number of the first of them in the file.
+    pub fn select(&self, text: &str) -> Result<(String, usize), Error> {
+        let mut lines: Vec<(usize, &str)> = text
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line))
+            .collect();
+
+        if let Some(ref anchor) = self.anchor {
+            let start = lines
+                .iter()
+                .position(|&(_, line)| anchor_name(line, "ANCHOR:") == Some(anchor.as_str()))
+                .ok_or_else(|| format_err!("There is no anchor named {:?}", anchor))?;
+            let end = lines[start..]
+                .iter()
+                .position(|&(_, line)| anchor_name(line, "ANCHOR_END:") == Some(anchor.as_str()))
+                .map(|end| start + end)
+                .unwrap_or_else(|| lines.len());
+            lines = lines[start + 1..end].to_vec();
+        }
+
+        let first = self.lines.0.unwrap_or(1);
+        let last = self.lines.1.unwrap_or(usize::MAX);
+        if first > last {
+            return Err(format_err!("The lines {}-{} are in the wrong order", first, last));
+        }
+        let lines: Vec<(usize, &str)> = lines
+            .into_iter()
+            .filter(|&(number, _)| first <= number && number <= last)
+            .filter(|&(_, line)| {
+                anchor_name(line, "ANCHOR:").is_none() && anchor_name(line, "ANCHOR_END:").is_none()
+            })
+            .collect();
+        let first_line = match lines.first() {
+            Some(&(number, _)) => number,
+            None => return Err(format_err!("There are no lines to include")),
+        };
+
+        let mut selected = String::new();
+        for (_, line) in lines {
+            selected.push_str(line);
+            selected.push('\n');
+        }
+        Ok((selected, first_line))
+    }
+}
+
+/// Reads the option `lines=` of an info string, which is either a line, like
+/// `10`, or an inclusive range, like `10-40`, `10-` or `-40`
+pub fn parse_lines(range: &str) -> Option<(Option<usize>, Option<usize>)> {
+    match range.find('-') {
+        Some(idx) => Some((parse_line(&range[..idx])?, parse_line(&range[idx + 1..])?)),
+        None => {
+            let line = parse_line(range)?;
+            Some((line, line))
+        }
+    }
+}
+
+/// Reads a line number that can be left out
+fn parse_line(line: &str) -> Option<Option<usize>> {
+    match line.trim() {
+        "" => Some(None),
+        line => line.parse().ok().filter(|&line| line > 0).map(Some),
+    }
+}
+
+/// The name of the anchor that a comment like `// ANCHOR: name` starts or
+/// ends, depending on the `marker`
+fn anchor_name<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let idx = line.find(marker)?;
+    line[idx + marker.len()..].split_whitespace().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "one
+// ANCHOR: all
+two
+// ANCHOR: middle
+three
+// ANCHOR_END: middle
+four
+// ANCHOR_END: all
+five
+// ANCHOR: open
+six
+";
+
+    fn directive(lines: (Option<usize>, Option<usize>), anchor: Option<&str>) -> Include {
+        Include {
+            path: "src/lib.rs".into(),
+            lines,
+            anchor: anchor.map(String::from),
+        }
+    }
+
+    #[test]
+    fn parses_directives() {
+        let parse = Include::parse_directive;
+        assert_eq!(parse("{{#include src/lib.rs}}"), Some(directive((None, None), None)));
+        assert_eq!(
+            parse("  {{#include src/lib.rs:10:40}} "),
+            Some(directive((Some(10), Some(40)), None))
+        );
+        assert_eq!(parse("{{#include src/lib.rs:10:}}"), Some(directive((Some(10), None), None)));
+        assert_eq!(parse("{{#include src/lib.rs::40}}"), Some(directive((None, Some(40)), None)));
+        assert_eq!(
+            parse("{{#include src/lib.rs:10}}"),
+            Some(directive((Some(10), Some(10)), None))
+        );
+        assert_eq!(
+            parse("{{#include src/lib.rs:name}}"),
+            Some(directive((None, None), Some("name")))
+        );
+    }
+
+    #[test]
+    fn rejects_broken_directives() {
+        assert_eq!(Include::parse_directive("{{#include}}"), None);
+        assert_eq!(Include::parse_directive("{{#include src/lib.rs"), None);
+        assert_eq!(Include::parse_directive("{{#include src/lib.rs:0:4}}"), None);
+        assert_eq!(Include::parse_directive("{{#include src/lib.rs:a:b}}"), None);
+        assert_eq!(Include::parse_directive("let x = 1;"), None);
+    }
+
+    #[test]
+    fn parses_line_ranges() {
+        assert_eq!(parse_lines("10-40"), Some((Some(10), Some(40))));
+        assert_eq!(parse_lines("10-"), Some((Some(10), None)));
+        assert_eq!(parse_lines("-40"), Some((None, Some(40))));
+        assert_eq!(parse_lines("10"), Some((Some(10), Some(10))));
+        assert_eq!(parse_lines("0"), None);
+        assert_eq!(parse_lines("ten"), None);
+    }
+
+    #[test]
+    fn selects_lines() {
+        let select = |lines| directive(lines, None).select(TEXT).unwrap();
+        assert_eq!(select((Some(3), Some(5))), ("two\nthree\n".to_string(), 3));
+        assert_eq!(select((Some(9), None)), ("five\nsix\n".to_string(), 9));
+        assert_eq!(select((None, Some(1))), ("one\n".to_string(), 1));
+        // The anchors themselves are left out
+        assert_eq!(select((None, None)).0, "one\ntwo\nthree\nfour\nfive\nsix\n");
+    }
+
+    #[test]
+    fn rejects_reversed_and_empty_ranges() {
+        assert!(directive((Some(5), Some(3)), None).select(TEXT).is_err());
+        assert!(directive((Some(20), None), None).select(TEXT).is_err());
+    }
+
+    #[test]
+    fn selects_anchors() {
+        let select = |anchor| directive((None, None), Some(anchor)).select(TEXT);
+        assert_eq!(select("all").unwrap(), ("two\nthree\nfour\n".to_string(), 3));
+        assert_eq!(select("middle").unwrap(), ("three\n".to_string(), 5));
+        // An anchor without an end runs to the end of the file
+        assert_eq!(select("open").unwrap(), ("six\n".to_string(), 11));
+        assert!(select("missing").is_err());
+    }
+
+    #[test]
+    fn selects_lines_of_anchors() {
+        let include = directive((Some(5), Some(7)), Some("all"));
+        assert_eq!(include.select(TEXT).unwrap(), ("three\nfour\n".to_string(), 5));
+    }
+}
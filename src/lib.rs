@@ -1,13 +1,23 @@
 #[macro_use]
 extern crate failure;
 extern crate image;
+extern crate lopdf;
 extern crate printpdf;
 extern crate pulldown_cmark as cmark;
 extern crate rusttype;
 #[macro_use]
 extern crate log;
 
+mod alignment;
 mod atomizer;
+mod code;
+mod document;
+mod heading;
+mod highlight;
+mod hyphenation;
+mod include;
+mod linebreak;
+mod list;
 mod page;
 mod pages;
 mod resources;
@@ -15,21 +25,35 @@ mod section;
 mod sectioner;
 mod sizer;
 mod span;
+mod strikethrough;
 mod style;
+mod table;
+mod template;
+mod toc;
 mod util;
 
 use crate::cmark::*;
 use failure::Error;
-use printpdf::{Image, Mm, PdfDocument, PdfDocumentReference};
+use printpdf::{Color, Image, Mm, PdfDocument, PdfLayerReference, Rgb};
 use rusttype::Scale;
 
 use crate::pages::Pages;
-use crate::resources::Loader;
+use crate::section::Section;
 use crate::sectioner::Sectioner;
 use crate::span::Span;
 use std::path::PathBuf;
 use crate::style::Class;
 
+pub use crate::alignment::TextAlignment;
+pub use crate::code::CodeOverflow;
+pub use crate::document::Document;
+pub use crate::highlight::SyntaxTheme;
+pub use crate::hyphenation::Hyphenator;
+pub use crate::linebreak::LineBreaking;
+pub use crate::list::NumberingStyle;
+pub use crate::resources::{Loader, Resources, SimpleLoader};
+pub use crate::template::PageTemplate;
+
 const DEFAULT_REGULAR_FONT: &str = "mdproof-default-regular";
 const DEFAULT_BOLD_FONT: &str = "mdproof-default-bold";
 const DEFAULT_ITALIC_FONT: &str = "mdproof-default-italic";
@@ -57,15 +81,131 @@ pub struct Config {
     pub h2_font_size: Scale,
     pub h3_font_size: Scale,
     pub h4_font_size: Scale,
+    pub h5_font_size: Scale,
+    pub h6_font_size: Scale,
+    pub footnote_font_size: Scale,
 
     pub line_spacing: f64, // Text height * LINE_SPACING
+    /// The alignment of body text
+    pub text_alignment: TextAlignment,
+    /// The alignment of headings, if it differs from `text_alignment`
+    pub heading_alignment: Option<TextAlignment>,
+    /// The alignment of the text of block quotes, if it differs from the text
+    /// around them
+    pub quote_alignment: Option<TextAlignment>,
+    /// The alignment of footnotes, if it differs from `text_alignment`
+    pub footnote_alignment: Option<TextAlignment>,
+    /// How paragraphs are broken into lines
+    pub line_breaking: LineBreaking,
+    /// Hyphenate words that don't fit at the end of a line. Soft hyphens in the
+    /// text are used even without this.
+    pub hyphenation: Option<Hyphenator>,
+    /// The minimum horizontal offset of the text of a list item. Lists whose
+    /// markers don't fit into this space are indented further.
     pub list_indentation: Mm,
-    pub list_point_offset: Mm,
+    /// The space between a list marker and the text of the list item
+    pub list_marker_spacing: Mm,
+    /// The bullets of unordered lists at each level of nesting
+    pub list_bullets: Vec<String>,
+    /// The numbering of ordered lists at each level of nesting. Lists that
+    /// are nested deeper than this start over from the first style.
+    pub ordered_list_styles: Vec<NumberingStyle>,
+    /// The marker of task list items that are not done yet
+    pub unchecked_task_marker: String,
+    /// The marker of task list items that are done
+    pub checked_task_marker: String,
+    /// Replace the markers of task list items with checkboxes that can be
+    /// ticked in a PDF viewer
+    pub task_list_checkboxes: bool,
     pub quote_indentation: Mm,
+    /// The width of the bar that runs down the left side of block quotes
+    pub quote_bar_width: Mm,
+    /// The color of the bar beside block quotes, or `None` to leave it out
+    pub quote_bar_color: Option<Color>,
+    /// The color of the text of block quotes, if it differs from black
+    pub quote_color: Option<Color>,
+    /// Set the text of block quotes in italics
+    pub quote_italic: bool,
     /// The horizontal offset of code blocks
     pub code_indentation: Mm,
+    /// The color of the box behind code blocks, or `None` to leave them
+    /// without a background
+    pub code_background: Option<Color>,
+    /// The color of the frame around code blocks, or `None` for no frame
+    pub code_border: Option<Color>,
+    /// The thickness of the frame around code blocks
+    pub code_border_width: Mm,
+    /// The space between the lines of a code block and the edges of its box
+    pub code_padding: Mm,
+    /// The colors of the tokens in code blocks whose language is known, or
+    /// `None` to leave code blocks black
+    pub syntax_theme: Option<SyntaxTheme>,
+    /// Number the lines of every code block, not only those whose info string
+    /// asks for it with `linenos`
+    pub code_line_numbers: bool,
+    /// The space between the line numbers of a code block and its lines
+    pub code_line_number_spacing: Mm,
+    /// The color of the band behind highlighted lines of code
+    pub code_highlight_color: Color,
+    /// What is done with lines of code that are too wide for the page
+    pub code_overflow: CodeOverflow,
+    /// The text put at the start of a wrapped line of code
+    pub code_continuation_marker: String,
+    /// How far the text of a wrapped line of code is indented, counting from
+    /// the start of the continuation marker
+    pub code_continuation_indentation: Mm,
+    /// The smallest a code block is shrunk to, relative to its font size,
+    /// when `code_overflow` is `CodeOverflow::Shrink`
+    pub code_min_font_scale: f64,
     /// The vertical space between two sections (paragraphs, lists, etc.)
     pub section_spacing: Mm,
+    /// The vertical space above headings of each level, starting at level 1.
+    /// It replaces the space below the section before the heading, if that is
+    /// smaller.
+    pub heading_space_before: [Mm; 6],
+    /// The vertical space below headings of each level, starting at level 1
+    pub heading_space_after: [Mm; 6],
+    /// How many lines of the section after a heading have to fit on the same
+    /// page as the heading. If they don't, the heading is moved to the next
+    /// page.
+    pub heading_keep_with_next: usize,
+    /// The fewest lines of a paragraph that may be left at the top of a page
+    /// when the paragraph is broken across pages
+    pub widows: usize,
+    /// The fewest lines of a paragraph that may be left at the bottom of a
+    /// page when the paragraph is broken across pages
+    pub orphans: usize,
+    /// The space between the text of a table cell and its border
+    pub table_cell_padding: Mm,
+    /// The thickness of the lines drawn around table cells
+    pub table_border_width: Mm,
+    /// The deepest level of headings that is included in the PDF outline
+    /// (bookmarks). `0` leaves out the outline entirely.
+    pub outline_depth: u8,
+    /// Put a table of contents at the start of the document. A table of
+    /// contents is always generated where the markdown contains `[[TOC]]`.
+    pub table_of_contents: bool,
+    /// The title put above the table of contents
+    pub table_of_contents_title: Option<String>,
+    /// The deepest level of headings that is listed in the table of contents
+    pub table_of_contents_depth: u8,
+    /// The line at the top of every page
+    pub header: PageTemplate,
+    /// The line at the bottom of every page
+    pub footer: PageTemplate,
+    /// The space between the header or footer and the body of the page
+    pub page_template_spacing: Mm,
+    /// The space between the body of a page and its footnotes, where a short
+    /// line separates the two
+    pub footnote_spacing: Mm,
+
+    /// Parse `~~strikethrough~~` text
+    pub strikethrough: bool,
+    /// Parse `- [ ]` and `- [x]` list items as tasks
+    pub task_lists: bool,
+    /// Turn straight quotes into curly quotes, `--` and `---` into dashes, and
+    /// `...` into an ellipsis
+    pub smart_punctuation: bool,
 }
 
 impl Default for Config {
@@ -89,18 +229,82 @@ impl Default for Config {
             h2_font_size: Scale::uniform(28.0),
             h3_font_size: Scale::uniform(20.0),
             h4_font_size: Scale::uniform(16.0),
+            h5_font_size: Scale::uniform(14.0),
+            h6_font_size: Scale::uniform(13.0),
+            footnote_font_size: Scale::uniform(9.0),
 
             line_spacing: 1.0, // Text height * LINE_SPACING
+            text_alignment: TextAlignment::Left,
+            heading_alignment: None,
+            quote_alignment: None,
+            footnote_alignment: None,
+            line_breaking: LineBreaking::Greedy,
+            hyphenation: None,
             list_indentation: Mm(10.0),
-            list_point_offset: Mm(5.0),
+            list_marker_spacing: Mm(2.5),
+            list_bullets: vec!["\u{2022}".into(), "\u{25E6}".into(), "\u{25AA}".into()],
+            ordered_list_styles: vec![
+                NumberingStyle::Decimal,
+                NumberingStyle::LowerAlpha,
+                NumberingStyle::LowerRoman,
+            ],
+            unchecked_task_marker: "\u{25A1}".into(),
+            checked_task_marker: "\u{25A0}".into(),
+            task_list_checkboxes: false,
             quote_indentation: Mm(20.0),
+            quote_bar_width: Mm(1.0),
+            quote_bar_color: Some(Color::Rgb(Rgb::new(0.7, 0.7, 0.7, None))),
+            quote_color: None,
+            quote_italic: false,
             code_indentation: Mm(10.0),
+            code_background: Some(Color::Rgb(Rgb::new(0.94, 0.94, 0.94, None))),
+            code_border: None,
+            code_border_width: Mm(0.3),
+            code_padding: Mm(2.0),
+            syntax_theme: Some(SyntaxTheme::light()),
+            code_line_numbers: false,
+            code_line_number_spacing: Mm(3.0),
+            code_highlight_color: Color::Rgb(Rgb::new(1.0, 0.95, 0.7, None)),
+            code_overflow: CodeOverflow::Wrap,
+            code_continuation_marker: "\u{2192}".into(),
+            code_continuation_indentation: Mm(6.0),
+            code_min_font_scale: 0.6,
             section_spacing: Mm(5.0),
+            heading_space_before: [Mm(12.0), Mm(10.0), Mm(8.0), Mm(6.0), Mm(6.0), Mm(6.0)],
+            heading_space_after: [Mm(5.0), Mm(4.0), Mm(3.0), Mm(3.0), Mm(2.0), Mm(2.0)],
+            heading_keep_with_next: 2,
+            widows: 2,
+            orphans: 2,
+            table_cell_padding: Mm(1.5),
+            table_border_width: Mm(0.3),
+            outline_depth: 3,
+            table_of_contents: false,
+            table_of_contents_title: Some("Contents".into()),
+            table_of_contents_depth: 3,
+            header: PageTemplate::default(),
+            footer: PageTemplate::default(),
+            page_template_spacing: Mm(5.0),
+            footnote_spacing: Mm(4.0),
+
+            strikethrough: true,
+            task_lists: true,
+            smart_punctuation: false,
         }
     }
 }
 
-pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentReference, Error> {
+pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<Document, Error> {
+    let mut loader = SimpleLoader::new(PathBuf::from(&cfg.resources_directory));
+    markdown_to_pdf_with_loader(markdown, cfg, &mut loader)
+}
+
+/// Like `markdown_to_pdf`, but the images and the files that are included
+/// into code blocks are read by `loader`
+pub fn markdown_to_pdf_with_loader(
+    markdown: &str,
+    cfg: &Config,
+    loader: &mut dyn Loader,
+) -> Result<Document, Error> {
     let (doc, mut page_idx, mut layer_idx) = PdfDocument::new(
         cfg.title.clone(),
         cfg.page_size.0,
@@ -108,19 +312,28 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
         cfg.first_layer_name.clone(),
     );
 
+    let mut links = Vec::new();
+    let mut checkboxes = Vec::new();
+    let headings;
+
     {
         let mut resources = resources::Resources::new(cfg.clone());
-        let atomizer = atomizer::Atomizer::new(Parser::new(&markdown));
+        let mut options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+        if cfg.strikethrough {
+            options.insert(Options::ENABLE_STRIKETHROUGH);
+        }
+        if cfg.task_lists {
+            options.insert(Options::ENABLE_TASKLISTS);
+        }
+        if cfg.smart_punctuation {
+            options.insert(Options::ENABLE_SMART_PUNCTUATION);
+        }
+        let atomizer = atomizer::Atomizer::new(Parser::new_ext(markdown, options), &*loader);
 
         let atoms: Vec<atomizer::Event> = atomizer.collect();
-        let mut loader = resources::SimpleLoader::new(PathBuf::from(&cfg.resources_directory));
         for event in atoms.iter() {
-            match event {
-                atomizer::Event::Atom(atomizer::Atom::Image { uri }) => {
-                    loader.queue_image(uri);
-                }
-
-                _ => {}
+            if let atomizer::Event::Atom(atomizer::Atom::Image { uri, .. }) = event {
+                loader.queue_image(uri);
             }
         }
 
@@ -128,20 +341,55 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
 
         let sized_atoms: Vec<_> = sizer::Sizer::new(atoms.into_iter(), &resources).collect();
 
-        let sections = {
+        let mut sections = {
             let mut lines =
                 Sectioner::new(cfg.margin.0, cfg.page_size.0 - cfg.margin.0, &resources);
 
             for event in sized_atoms {
-                lines.parse_event(&resources, event);
+                lines.parse_event(event);
             }
 
             lines.get_vec()
         };
 
+        let has_toc_marker = sections
+            .iter()
+            .any(|s| matches!(s, Section::TableOfContents));
+        if has_toc_marker || cfg.table_of_contents {
+            let toc = toc::table_of_contents(
+                &resources,
+                &sections,
+                cfg.margin.0,
+                cfg.page_size.0 - cfg.margin.0,
+            );
+            sections = if has_toc_marker {
+                sections
+                    .into_iter()
+                    .flat_map(|section| match section {
+                        Section::TableOfContents => toc.clone(),
+                        section => vec![section],
+                    })
+                    .collect()
+            } else {
+                toc.into_iter()
+                    .chain(Some(Section::page_break()))
+                    .chain(sections)
+                    .collect()
+            };
+
+            // The entries take up the same space with or without page numbers,
+            // so laying the document out once tells where every heading ends up
+            let mut pages = Pages::new(cfg, &resources);
+            pages.set_footnotes(&sections);
+            pages.render_sections(&sections[..], cfg.margin.0);
+            toc::set_page_numbers(&mut sections, pages.headings());
+        }
+
         let mut pages = Pages::new(cfg, &resources);
+        pages.set_footnotes(&sections);
         pages.render_sections(&sections[..], cfg.margin.0);
 
+        headings = pages.headings().to_vec();
         let pages = pages.into_vec();
 
         let default_font_reader = std::io::Cursor::new(resources::REGULAR_FONT);
@@ -168,7 +416,7 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
 
         let mut is_first_iteration = true;
 
-        for page in pages {
+        for (idx, page) in pages.into_iter().enumerate() {
             if !is_first_iteration {
                 let (new_page_idx, new_layer_idx) =
                     doc.add_page(cfg.page_size.0, cfg.page_size.1, "Layer 1");
@@ -177,16 +425,29 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
             }
 
             let current_layer = doc.get_page(page_idx).get_layer(layer_idx);
-            let page = page.into_vec().into_iter().peekable();
-            for span in page {
-                current_layer.begin_text_section();
-                current_layer.set_text_cursor(span.pos.0, span.pos.1);
+            let page = page.into_vec();
+            links.extend(document::find_links(&resources, idx, &page));
+            let strikethroughs = strikethrough::find_strikethroughs(&resources, &page);
+            if cfg.task_list_checkboxes {
+                checkboxes.extend(document::find_checkboxes(&resources, idx, &page));
+            }
 
+            let page = page.into_iter().peekable();
+            for span in page {
                 match span.span {
+                    // Gaps and hyphens only take up space
+                    Span::Gap { .. } | Span::Hyphen { .. } => {}
+                    // The checkbox takes the place of the marker
+                    Span::Text { ref style, .. }
+                        if cfg.task_list_checkboxes && style.checkbox().is_some() => {}
                     Span::Text { text, style, .. } => {
+                        current_layer.begin_text_section();
+                        current_layer.set_text_cursor(span.pos.0, span.pos.1);
+
                         // TODO: Abstract this piece of code away. It violates DRY.
                         let strong = style.contains(&Class::Strong);
-                        let emphasis = style.contains(&Class::Emphasis);
+                        let emphasis = style.contains(&Class::Emphasis)
+                            || (cfg.quote_italic && style.quote_level().is_some());
 
                         let font = if style.contains(&Class::Code) {
                             &mono
@@ -200,12 +461,27 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
                             &regular
                         };
 
-                        let font_scale = util::scale_from_style(&cfg, &style);
+                        let font_scale = util::scale_from_style(cfg, &style);
+
+                        let color = match (&cfg.syntax_theme, style.token()) {
+                            (Some(theme), Some(token)) => Some(theme.color(token).clone()),
+                            _ if style.quote_level().is_some() => cfg.quote_color.clone(),
+                            _ => None,
+                        };
 
                         current_layer.set_font(font, font_scale.y as i64);
+                        if let Some(ref color) = color {
+                            current_layer.set_fill_color(color.clone());
+                        }
                         current_layer.write_text(text, font);
+                        if color.is_some() {
+                            current_layer.set_fill_color(black());
+                        }
+                        current_layer.end_text_section();
                     }
                     Span::Image { path, .. } => {
+                        current_layer.begin_text_section();
+                        current_layer.set_text_cursor(span.pos.0, span.pos.1);
                         let image = Image::from_dynamic_image(
                             resources
                                 .get_image(&path.to_string_lossy())
@@ -220,30 +496,55 @@ pub fn markdown_to_pdf(markdown: &str, cfg: &Config) -> Result<PdfDocumentRefere
                             None,
                             None,
                         );
+                        current_layer.end_text_section();
                     }
-                    Span::Rect { width, height } => {
-                        use printpdf::{Line, Point};
-                        let rect_points = vec![
-                            (Point::new(span.pos.0, span.pos.1 + height), false),
-                            (Point::new(span.pos.0 + width, span.pos.1 + height), false),
-                            (Point::new(span.pos.0 + width, span.pos.1), false),
-                            (Point::new(span.pos.0, span.pos.1), false),
-                        ];
-                        let rect = Line {
-                            points: rect_points,
-                            is_closed: true,
-                            has_fill: true,
-                            has_stroke: false,
-                            is_clipping_path: false,
-                        };
-                        current_layer.add_shape(rect);
+                    Span::Rect {
+                        width,
+                        height,
+                        color,
+                    } => {
+                        current_layer.begin_text_section();
+                        current_layer.set_text_cursor(span.pos.0, span.pos.1);
+                        draw_rect(&current_layer, span.pos, width, height, color);
+                        current_layer.end_text_section();
                     }
                 }
-                current_layer.end_text_section();
+            }
+            for line in strikethroughs {
+                draw_rect(&current_layer, line.pos, line.width, line.thickness, black());
             }
             is_first_iteration = false;
         }
     }
 
-    Ok(doc)
+    Ok(Document::new(doc, links, checkboxes, headings, cfg.outline_depth))
+}
+
+/// Draws a filled rectangle with its lower left corner at `pos`
+fn draw_rect(layer: &PdfLayerReference, pos: (Mm, Mm), width: Mm, height: Mm, color: Color) {
+    use printpdf::{Line, Point};
+    let rect_points = vec![
+        (Point::new(pos.0, pos.1 + height), false),
+        (Point::new(pos.0 + width, pos.1 + height), false),
+        (Point::new(pos.0 + width, pos.1), false),
+        (Point::new(pos.0, pos.1), false),
+    ];
+    let rect = Line {
+        points: rect_points,
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    };
+    // Text is filled in the same color, so it is set back to black afterwards
+    let is_black = color == black();
+    layer.set_fill_color(color);
+    layer.add_shape(rect);
+    if !is_black {
+        layer.set_fill_color(black());
+    }
+}
+
+fn black() -> Color {
+    Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None))
 }
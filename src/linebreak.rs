@@ -0,0 +1,193 @@
+use printpdf::Mm;
+use crate::resources::Resources;
+use crate::span::Span;
+use crate::util::width_of_text;
+
+/// How the text of a paragraph is broken into lines
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineBreaking {
+    /// Words are put on the current line until the next one doesn't fit. This
+    /// is the fastest way of breaking lines.
+    Greedy,
+    /// The breaks are chosen for the paragraph as a whole, so that the lines
+    /// are as even as possible (Knuth and Plass' total fit algorithm)
+    Optimal,
+}
+
+/// How far a space may be stretched, relative to its width, before the line
+/// counts as loose
+const STRETCH: f64 = 0.5;
+/// The badness of a word that is wider than a line on its own
+const OVERFULL_BADNESS: f64 = 10_000.0;
+/// The badness of lines that have no spaces to stretch, which are only used when
+/// the next word doesn't fit
+const RIGID_BADNESS: f64 = 1e12;
+/// The cost of every line, so that fewer lines are preferred
+const LINE_PENALTY: f64 = 10.0;
+/// The extra cost of a line that ends in a hyphenated word
+const HYPHEN_PENALTY: f64 = 50.0;
+
+/// Splits the spans of a paragraph into lines no wider than `width`, picking the
+/// breaks that give the least total demerits. Lines are only broken at spaces,
+/// which are left out, and at hyphens, which are put at the end of the line.
+pub fn break_lines(resources: &Resources, spans: Vec<Span>, width: Mm) -> Vec<Vec<Span>> {
+    // Running totals of the natural width and the stretchability of the spans
+    let mut widths = vec![0.0];
+    let mut stretches = vec![0.0];
+    for span in spans.iter() {
+        let span_width = span.width(resources).0;
+        let stretch = if span.is_space() {
+            span_width * STRETCH
+        } else {
+            0.0
+        };
+        widths.push(widths[widths.len() - 1] + span_width);
+        stretches.push(stretches[stretches.len() - 1] + stretch);
+    }
+
+    // Every line ends at a space, a hyphen or at the end of the paragraph
+    let mut ends: Vec<usize> = spans
+        .iter()
+        .enumerate()
+        .filter(|(_, span)| span.is_space() || hyphen_width(resources, span).is_some())
+        .map(|(idx, _)| idx)
+        .collect();
+    ends.push(spans.len());
+
+    // The least demerits of the paragraph up to each end, and the end of the
+    // line before it. `None` stands for the start of the paragraph.
+    let mut best: Vec<(f64, Option<usize>)> = Vec::with_capacity(ends.len());
+    for (j, &end) in ends.iter().enumerate() {
+        let last_line = end == spans.len();
+        let hyphen = spans.get(end).and_then(|span| hyphen_width(resources, span));
+        let mut choice = (f64::INFINITY, None);
+        for i in (0..=j).rev() {
+            let (previous, start) = match i {
+                0 => (0.0, 0),
+                i => (best[i - 1].0, ends[i - 1] + 1),
+            };
+            let natural = widths[end] - widths[start] + hyphen.unwrap_or(0.0);
+            let badness = if natural > width.0 {
+                // A word that is wider than the line has to go somewhere
+                if i < j {
+                    break;
+                }
+                OVERFULL_BADNESS
+            } else if last_line {
+                0.0
+            } else {
+                let stretch = stretches[end] - stretches[start];
+                if stretch > 0.0 {
+                    // Lines that have to be stretched further than they
+                    // should are not capped, so that the loosest line of the
+                    // paragraph is still kept as tight as possible
+                    let ratio = (width.0 - natural) / stretch;
+                    100.0 * ratio.powi(3)
+                } else {
+                    RIGID_BADNESS
+                }
+            };
+            let mut demerits = previous + (LINE_PENALTY + badness).powi(2);
+            if hyphen.is_some() {
+                demerits += HYPHEN_PENALTY.powi(2);
+            }
+            if demerits < choice.0 {
+                choice = (demerits, i.checked_sub(1));
+            }
+        }
+        best.push(choice);
+    }
+
+    let mut breaks = vec![ends.len() - 1];
+    while let Some(previous) = best[breaks[breaks.len() - 1]].1 {
+        breaks.push(previous);
+    }
+    breaks.reverse();
+
+    let mut breaks = breaks.into_iter().map(|idx| ends[idx]).peekable();
+    let mut lines = vec![Vec::new()];
+    for (idx, span) in spans.into_iter().enumerate() {
+        let last = lines.len() - 1;
+        if breaks.peek() == Some(&idx) {
+            breaks.next();
+            if let Span::Hyphen { style } = span {
+                lines[last].push(Span::text("-".into(), style));
+            }
+            lines.push(Vec::new());
+        } else if let Span::Hyphen { .. } = span {
+            // The word isn't hyphenated here
+        } else {
+            lines[last].push(span);
+        }
+    }
+    lines
+}
+
+/// The width of the hyphen that `span` turns into if the line is broken there,
+/// if it is a place where a word can be hyphenated
+fn hyphen_width(resources: &Resources, span: &Span) -> Option<f64> {
+    match span {
+        Span::Hyphen { style } => {
+            let width: Mm = width_of_text(resources, style, "-").into();
+            Some(width.0)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    /// A paragraph of words that are as wide as the given number of spaces
+    fn paragraph(space: Mm, words: &[f64]) -> Vec<Span> {
+        let mut spans = Vec::new();
+        for (idx, &word) in words.iter().enumerate() {
+            if idx > 0 {
+                spans.push(Span::text(" ".into(), Default::default()));
+            }
+            spans.push(Span::rect(space * word, Mm(1.0)));
+        }
+        spans
+    }
+
+    /// The widths of the words on each line, in spaces
+    fn words(space: Mm, lines: &[Vec<Span>]) -> Vec<Vec<f64>> {
+        lines
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .filter_map(|span| match span {
+                        Span::Rect { width, .. } => Some((width.0 / space.0).round()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn evens_out_the_lines() {
+        let resources = Resources::new(Config::default());
+        let space = Span::text(" ".into(), Default::default()).width(&resources);
+        let spans = paragraph(space, &[2.0, 5.0, 2.0, 3.0, 3.0, 5.0]);
+
+        // Breaking greedily leaves a short second line
+        let greedy = vec![vec![2.0, 5.0, 2.0], vec![3.0, 3.0], vec![5.0]];
+        let optimal = vec![vec![2.0, 5.0], vec![2.0, 3.0, 3.0], vec![5.0]];
+        let lines = break_lines(&resources, spans, space * 12.0);
+        assert_ne!(words(space, &lines), greedy);
+        assert_eq!(words(space, &lines), optimal);
+    }
+
+    #[test]
+    fn puts_words_wider_than_the_line_on_their_own() {
+        let resources = Resources::new(Config::default());
+        let space = Span::text(" ".into(), Default::default()).width(&resources);
+        let spans = paragraph(space, &[2.0, 20.0, 2.0]);
+
+        let lines = break_lines(&resources, spans, space * 12.0);
+        assert_eq!(words(space, &lines), vec![vec![2.0], vec![20.0], vec![2.0]]);
+    }
+}
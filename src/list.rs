@@ -0,0 +1,67 @@
+/// How the items of an ordered list are numbered
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NumberingStyle {
+    /// 1, 2, 3, ...
+    Decimal,
+    /// a, b, c, ..., z, aa, ab, ...
+    LowerAlpha,
+    /// A, B, C, ..., Z, AA, AB, ...
+    UpperAlpha,
+    /// i, ii, iii, iv, ...
+    LowerRoman,
+    /// I, II, III, IV, ...
+    UpperRoman,
+}
+
+impl NumberingStyle {
+    /// Formats `number` in this style. Alphabetic and roman numbering have no
+    /// zero, so zero always falls back to decimal.
+    pub fn format(self, number: usize) -> String {
+        if number == 0 {
+            return number.to_string();
+        }
+        match self {
+            NumberingStyle::Decimal => number.to_string(),
+            NumberingStyle::LowerAlpha => to_alpha(number),
+            NumberingStyle::UpperAlpha => to_alpha(number).to_uppercase(),
+            NumberingStyle::LowerRoman => to_roman(number),
+            NumberingStyle::UpperRoman => to_roman(number).to_uppercase(),
+        }
+    }
+}
+
+fn to_alpha(mut number: usize) -> String {
+    let mut letters = Vec::new();
+    while number > 0 {
+        number -= 1;
+        letters.push((b'a' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn to_roman(mut number: usize) -> String {
+    const NUMERALS: &[(usize, &str)] = &[
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut roman = String::new();
+    for &(value, numeral) in NUMERALS {
+        while number >= value {
+            roman.push_str(numeral);
+            number -= value;
+        }
+    }
+    roman
+}
@@ -1,6 +1,7 @@
 use printpdf::Mm;
 use crate::resources::Resources;
 use crate::span::{PositionedSpan, Span};
+use crate::util::baseline_offset;
 
 #[derive(Clone)]
 pub struct Page {
@@ -24,12 +25,27 @@ impl Page {
         let mut x = start_x;
         let y = start_y;
         for span in spans {
+            let y = match span {
+                Span::Text { style, .. } => y + baseline_offset(resources.get_config(), style).into(),
+                _ => y,
+            };
             self.positioned_spans
                 .push(PositionedSpan::new(span.clone(), x, y));
             x += span.width(resources);
         }
     }
 
+    /// The number of spans that have been put on the page so far
+    pub fn span_count(&self) -> usize {
+        self.positioned_spans.len()
+    }
+
+    /// Puts `span` in front of the span at `idx`, so that it is drawn beneath
+    /// the spans that come after it
+    pub fn insert(&mut self, idx: usize, span: PositionedSpan) {
+        self.positioned_spans.insert(idx, span);
+    }
+
     pub fn clear(&mut self) {
         self.positioned_spans.clear();
     }
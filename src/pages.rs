@@ -1,51 +1,515 @@
 use super::Config;
+use crate::heading::HeadingPosition;
 use crate::page::Page;
 use printpdf::Mm;
 use crate::resources::Resources;
 use crate::section::Section;
-use crate::span::Span;
-use crate::style::Class;
+use crate::span::{PositionedSpan, Span};
+use crate::style::{Class, Style};
+use crate::template::TemplateValues;
+use crate::table::{line_height, line_width, Table, TableRow};
+use crate::toc::TocEntry;
+use crate::util::{font_ascent_descent, width_of_text};
+use crate::cmark::Alignment;
+use std::collections::{HashMap, HashSet};
 
 pub struct Pages<'res> {
     pages: Vec<Page>,
     current_page: Page,
     current_y: Mm,
+    /// The top of the area of a page that the body is put into, below the header
+    body_top: Mm,
+    /// The bottom of the area of a page that the body is put into, above the
+    /// footer
+    body_bottom: Mm,
+    headings: Vec<HeadingPosition>,
+    /// The lines of every footnote, by number
+    footnotes: HashMap<usize, Vec<Section>>,
+    /// The footnotes that have already been put on a page
+    placed_footnotes: HashSet<usize>,
+    /// The lines of the footnotes that go at the bottom of the current page
+    notes: Vec<Section>,
+    /// The horizontal offset and the top on the current page of the bars of
+    /// the block quotes that are being rendered, from the outermost inwards
+    quote_bars: Vec<(Mm, Mm)>,
     cfg: &'res Config,
     resources: &'res Resources,
 }
 
 impl<'res> Pages<'res> {
     pub fn new(cfg: &'res Config, resources: &'res Resources) -> Self {
+        let body_top = cfg.page_size.1 - cfg.margin.1 - cfg.header.height(resources);
+        let body_bottom = cfg.margin.1 + cfg.footer.height(resources);
         Self {
-            cfg: cfg,
-            resources: resources,
+            cfg,
+            resources,
             pages: vec![],
             current_page: Page::new(),
-            current_y: cfg.page_size.1 - cfg.margin.1,
+            current_y: body_top,
+            body_top,
+            body_bottom,
+            headings: vec![],
+            footnotes: HashMap::new(),
+            placed_footnotes: HashSet::new(),
+            notes: vec![],
+            quote_bars: vec![],
+        }
+    }
+
+    /// Remembers the footnote definitions in `sections`, so that they can be
+    /// put on the page where they are referenced
+    pub fn set_footnotes(&mut self, sections: &[Section]) {
+        for section in sections {
+            match section {
+                Section::Footnote(number, lines) => {
+                    // Footnotes are split line by line at the bottom of pages,
+                    // so their paragraphs are taken apart again
+                    let lines = lines
+                        .iter()
+                        .flat_map(|line| match line {
+                            Section::Paragraph(lines) => lines.clone(),
+                            line => vec![line.clone()],
+                        })
+                        .collect();
+                    self.footnotes.insert(*number, lines);
+                }
+                Section::ListItem(_, _, sections) | Section::BlockQuote(sections) => {
+                    self.set_footnotes(sections)
+                }
+                _ => {}
+            }
         }
     }
 
     fn new_page(&mut self) {
+        // The bars of unfinished quotes run to the bottom of the page, and are
+        // continued on the next one
+        for idx in 0..self.quote_bars.len() {
+            let (x, top) = self.quote_bars[idx];
+            self.render_quote_bar(x, top);
+            self.quote_bars[idx].1 = self.body_top;
+        }
+        self.render_notes();
+        self.next_page();
+    }
+
+    fn next_page(&mut self) {
         self.pages.push(self.current_page.clone());
         self.current_page.clear();
-        self.current_y = self.cfg.page_size.1 - self.cfg.margin.1;
+        self.current_y = self.body_top;
     }
 
-    pub fn render_sections(&mut self, sections: &[Section], start_x: Mm) {
-        let min_y = self.cfg.margin.1;
+    /// The lowest point that the body of the current page may reach, leaving
+    /// room for its footnotes
+    fn min_y(&self) -> Mm {
+        self.body_bottom + self.notes_height(&self.notes, true)
+    }
+
+    /// The height of footnote lines, including the separator above them if
+    /// `with_separator` is set
+    fn notes_height(&self, notes: &[Section], with_separator: bool) -> Mm {
+        if notes.is_empty() {
+            return Mm(0.0);
+        }
+        let lines = notes
+            .iter()
+            .fold(Mm(0.0), |acc, line| acc + line.height(self.resources));
+        let separator = if with_separator {
+            self.cfg.footnote_spacing
+        } else {
+            Mm(0.0)
+        };
+        lines * self.cfg.line_spacing + separator
+    }
+
+    /// The lines of the footnotes that are referenced in `spans` for the first
+    /// time. References are superscript numbers outside of footnotes.
+    fn referenced_notes<'s, I>(&self, spans: I) -> Vec<(usize, Vec<Section>)>
+    where
+        I: IntoIterator<Item = &'s Span>,
+    {
+        let mut notes: Vec<(usize, Vec<Section>)> = Vec::new();
+        for span in spans {
+            if let Span::Text { text, style } = span {
+                if !style.contains(&Class::Superscript) || style.contains(&Class::Note) {
+                    continue;
+                }
+                let number = match text.parse::<usize>() {
+                    Ok(number) => number,
+                    Err(_) => continue,
+                };
+                if self.placed_footnotes.contains(&number)
+                    || notes.iter().any(|&(n, _)| n == number)
+                {
+                    continue;
+                }
+                match self.footnotes.get(&number) {
+                    Some(lines) => notes.push((number, lines.clone())),
+                    None => warn!("Footnote {} is referenced, but never defined", number),
+                }
+            }
+        }
+        notes
+    }
+
+    /// The room that the footnotes which `spans` refer to for the first time
+    /// take up at the bottom of the page
+    fn new_notes_height<'s, I>(&self, spans: I) -> Mm
+    where
+        I: IntoIterator<Item = &'s Span>,
+    {
+        let lines: Vec<Section> = self
+            .referenced_notes(spans)
+            .into_iter()
+            .flat_map(|(_, lines)| lines)
+            .collect();
+        self.notes_height(&lines, self.notes.is_empty())
+    }
+
+    /// Puts the footnotes at the bottom of the current page. Lines that don't
+    /// fit between the body and the bottom margin are carried over to the
+    /// next page.
+    fn render_notes(&mut self) {
+        if self.notes.is_empty() {
+            return;
+        }
+        let notes = std::mem::take(&mut self.notes);
+        let height = self.notes_height(&notes, true);
+        let fits = self.body_bottom + height <= self.current_y;
+        let top = if fits {
+            self.body_bottom + height
+        } else {
+            self.current_y
+        };
+
+        let spacing = self.cfg.footnote_spacing;
+        let width = (self.cfg.page_size.0 - self.cfg.margin.0 * 2.0) / 3.0;
+        let separator = Span::rect(width, Mm(0.2));
+        self.current_page.render_spans(
+            self.resources,
+            &[separator],
+            self.cfg.margin.0,
+            top - spacing / 2.0,
+        );
+
+        let mut y = top - spacing;
+        for (idx, line) in notes.iter().enumerate() {
+            let step = line.height(self.resources) * self.cfg.line_spacing;
+            // At least one line is put on every page, so that long notes
+            // always make progress
+            if !fits && idx > 0 && y - step < self.body_bottom {
+                self.notes = notes[idx..].to_vec();
+                break;
+            }
+            y -= step;
+            match line {
+                Section::Plain(spans) => {
+                    self.current_page
+                        .render_spans(self.resources, spans, self.cfg.margin.0, y)
+                }
+                Section::VerticalSpace(_) => {}
+                section => warn!("Unsupported section inside of a footnote: {:?}", section),
+            }
+        }
+        self.current_y = y;
+    }
+
+    /// The height of the first `lines` lines of `sections`, along with the space
+    /// and headings in between them. Returns the height and the number of lines
+    /// that were found.
+    fn leading_height(&self, sections: &[Section], lines: usize) -> (Mm, usize) {
+        let mut height = Mm(0.0);
+        let mut found = 0;
         for section in sections {
+            if found >= lines {
+                break;
+            }
+            let step = |h: Mm| h * self.cfg.line_spacing;
+            match section {
+                Section::PageBreak => break,
+                Section::VerticalSpace(_) => height += step(section.height(self.resources)),
+                Section::Plain(_) | Section::ThematicBreak => {
+                    height += step(section.height(self.resources));
+                    found += 1;
+                }
+                // Headings are kept with whatever follows them, so they don't
+                // count towards the lines
+                Section::Heading(_heading, _lines) => height += step(section.height(self.resources)),
+                Section::Paragraph(paragraph) => {
+                    // Paragraphs are only broken where enough of their lines
+                    // are left on both pages, so more of them may have to come
+                    // along
+                    let (widows, orphans) = (self.cfg.widows.max(1), self.cfg.orphans.max(1));
+                    let mut needed = (lines - found).max(orphans);
+                    if paragraph.len() < needed + widows {
+                        needed = paragraph.len();
+                    }
+                    let (h, n) = self.leading_height(paragraph, needed);
+                    height += h;
+                    found += n;
+                }
+                Section::ListItem(_, _, sections) | Section::BlockQuote(sections) => {
+                    let (h, n) = self.leading_height(sections, lines - found);
+                    height += h;
+                    found += n;
+                }
+                Section::TocEntry(entry) => {
+                    let (h, n) = self.leading_height(&entry.lines, lines - found);
+                    height += h;
+                    found += n;
+                }
+                Section::CodeBlock(code_lines, _) => {
+                    for line in code_lines.iter().take(lines - found) {
+                        height += step(line_height(self.resources, line));
+                        found += 1;
+                    }
+                }
+                Section::Table(table) => {
+                    for row in table.head.iter().chain(table.rows.iter()).take(lines - found) {
+                        height += row.height(self.resources);
+                        found += 1;
+                    }
+                }
+                Section::TableOfContents | Section::Footnote(_, _) => {}
+            }
+        }
+        (height, found)
+    }
+
+    /// The height of the part of `section` that has to fit on the page that
+    /// the section starts on
+    fn min_height(&self, section: &Section) -> Mm {
+        match section {
+            Section::Paragraph(lines) => {
+                let (widows, orphans) = (self.cfg.widows.max(1), self.cfg.orphans.max(1));
+                // Short paragraphs can't be broken without leaving too few
+                // lines on one of the pages
+                let keep = if lines.len() < widows + orphans {
+                    lines.len()
+                } else {
+                    orphans
+                };
+                lines
+                    .iter()
+                    .take(keep)
+                    .fold(Mm(0.0), |acc, line| acc + line.height(self.resources))
+            }
+            Section::ListItem(_, _, sections) | Section::BlockQuote(sections) => sections
+                .first()
+                .map(|section| self.min_height(section))
+                .unwrap_or(Mm(0.0)),
+            section => section.min_step(self.resources),
+        }
+    }
+
+    /// Renders the lines of a paragraph, breaking it so that at least
+    /// `Config::orphans` lines are left at the bottom of a page and at least
+    /// `Config::widows` lines are carried over to the next one
+    fn render_paragraph(&mut self, lines: &[Section], start_x: Mm) {
+        let (widows, orphans) = (self.cfg.widows.max(1), self.cfg.orphans.max(1));
+        let mut fitting = 0;
+        let mut y = self.current_y;
+        for (idx, line) in lines.iter().enumerate() {
+            y -= line.height(self.resources) * self.cfg.line_spacing;
+            // The footnotes that the lines refer to go on the same page
+            let reserved = self.new_notes_height(lines[..=idx].iter().flat_map(section_spans));
+            if y < self.min_y() + reserved {
+                break;
+            }
+            fitting += 1;
+        }
+        if fitting == lines.len() {
+            self.render_sections(lines, start_x);
+            return;
+        }
+
+        let mut split = fitting.min(lines.len().saturating_sub(widows));
+        if split < orphans {
+            if self.current_y < self.body_top {
+                self.new_page();
+                self.render_paragraph(lines, start_x);
+                return;
+            }
+            // The paragraph doesn't fit on an empty page either, so the rules
+            // can't be kept
+            split = fitting.max(1);
+        }
+        self.render_sections(&lines[..split], start_x);
+        self.new_page();
+        self.render_paragraph(&lines[split..], start_x);
+    }
+
+    /// Renders the lines of a code block inside of a box, drawing a band
+    /// behind the `highlighted` lines. A block that is broken across pages gets
+    /// a box of its own on every page.
+    fn render_code_block(&mut self, lines: &[Vec<Span>], highlighted: &[usize], start_x: Mm) {
+        let padding = self.cfg.code_padding;
+        let code_x = start_x + self.cfg.code_indentation;
+        // The index of the first span of the part of the block on this page,
+        // and the top of its box
+        let mut fragment: Option<(usize, Mm)> = None;
+        let mut descent = Mm(0.0);
+        for (idx, line) in lines.iter().enumerate() {
+            let height = line_height(self.resources, line) * self.cfg.line_spacing;
+            let needed = height - line_descent(self.resources, line) + padding;
+            let top_padding = if fragment.is_some() { Mm(0.0) } else { padding };
+            let fits = self.current_y - top_padding - needed >= self.min_y();
+            if !fits && (fragment.is_some() || self.current_y < self.body_top) {
+                if let Some((idx, top)) = fragment.take() {
+                    self.end_code_fragment(idx, top, descent, code_x);
+                }
+                self.new_page();
+            }
+
+            if fragment.is_none() {
+                fragment = Some((self.current_page.span_count(), self.current_y));
+                self.current_y -= padding;
+            }
+            self.current_y -= height;
+            descent = line_descent(self.resources, line);
+            if highlighted.contains(&idx) {
+                let left = code_x - padding;
+                let width = self.cfg.page_size.0 - self.cfg.margin.0 - left;
+                let band = Span::colored_rect(width, height, self.cfg.code_highlight_color.clone());
+                self.current_page
+                    .render_spans(self.resources, &[band], left, self.current_y + descent);
+            }
+            self.current_page
+                .render_spans(self.resources, line, code_x, self.current_y);
+        }
+        if let Some((idx, top)) = fragment {
+            self.end_code_fragment(idx, top, descent, code_x);
+        }
+    }
+
+    /// Puts the box of the part of a code block that ends on this page beneath
+    /// its lines, which start at span `idx` of the page. `descent` is how far
+    /// the last line reaches below its baseline.
+    fn end_code_fragment(&mut self, idx: usize, top: Mm, descent: Mm, code_x: Mm) {
+        let bottom = self.current_y + descent - self.cfg.code_padding;
+        self.current_y = bottom;
+
+        let left = code_x - self.cfg.code_padding;
+        let width = self.cfg.page_size.0 - self.cfg.margin.0 - left;
+        let height = top - bottom;
+        let mut rects = Vec::new();
+        if let Some(ref color) = self.cfg.code_background {
+            rects.push((Span::colored_rect(width, height, color.clone()), left, bottom));
+        }
+        if let Some(ref color) = self.cfg.code_border {
+            let border = self.cfg.code_border_width;
+            let horizontal = Span::colored_rect(width, border, color.clone());
+            let vertical = Span::colored_rect(border, height, color.clone());
+            rects.push((horizontal.clone(), left, bottom));
+            rects.push((horizontal, left, top - border));
+            rects.push((vertical.clone(), left, bottom));
+            rects.push((vertical, left + width - border, bottom));
+        }
+        for (offset, (rect, x, y)) in rects.into_iter().enumerate() {
+            self.current_page
+                .insert(idx + offset, PositionedSpan::new(rect, x, y));
+        }
+    }
+
+    /// Renders the sections of a block quote, along with the bar beside them
+    fn render_block_quote(&mut self, sections: &[Section], start_x: Mm) {
+        // The space after the last paragraph is left out of the bar
+        let end = sections
+            .iter()
+            .rposition(|section| !matches!(section, Section::VerticalSpace(_)))
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        let text_x = start_x + self.cfg.quote_indentation;
+
+        self.quote_bars.push((start_x, self.current_y));
+        self.render_sections(&sections[..end], text_x);
+        if let Some((x, top)) = self.quote_bars.pop() {
+            self.render_quote_bar(x, top);
+        }
+        self.render_sections(&sections[end..], text_x);
+    }
+
+    /// Draws the bar of a block quote from `top` down to the last line that
+    /// was rendered on the current page
+    fn render_quote_bar(&mut self, x: Mm, top: Mm) {
+        let color = match self.cfg.quote_bar_color {
+            Some(ref color) => color.clone(),
+            None => return,
+        };
+        if self.current_y >= top {
+            return;
+        }
+        let descent: Mm = font_ascent_descent(self.resources, &Style::default()).1.into();
+        let bottom = self.current_y + descent;
+        let bar = Span::colored_rect(self.cfg.quote_bar_width, top - bottom, color);
+        self.current_page
+            .render_spans(self.resources, &[bar], x, bottom);
+    }
+
+    pub fn render_sections(&mut self, sections: &[Section], start_x: Mm) {
+        for (idx, section) in sections.iter().enumerate() {
             trace!("rendering section: {:?}", section);
+
+            if let Section::Paragraph(lines) = section {
+                self.render_paragraph(lines, start_x);
+                continue;
+            }
+            if let Section::CodeBlock(lines, highlighted) = section {
+                self.render_code_block(lines, highlighted, start_x);
+                continue;
+            }
+
             let height = section.min_step(self.resources);
             let delta_y = height * -self.cfg.line_spacing;
-            if self.current_y + delta_y < min_y {
+
+            // Make room for the footnotes that the line refers to, on the same
+            // page as the line
+            let new_notes = self.referenced_notes(section_spans(section));
+            let new_lines: Vec<Section> = new_notes
+                .iter()
+                .flat_map(|(_, lines)| lines.iter().cloned())
+                .collect();
+            let mut reserved = self.notes_height(&new_lines, self.notes.is_empty());
+            if height * self.cfg.line_spacing + reserved > self.body_top - self.body_bottom {
+                // The notes don't fit on any page along with the line, so they
+                // are started below the line and carried over to the next page
+                let first_line = new_lines.get(..1).unwrap_or(&[]);
+                reserved = self.notes_height(first_line, self.notes.is_empty());
+            }
+
+            // Don't leave a heading alone at the bottom of a page
+            if let Section::Heading(_heading, _lines) = section {
+                let keep = self.cfg.heading_keep_with_next;
+                let (following, _) = self.leading_height(&sections[idx + 1..], keep);
+                let needed = section.height(self.resources) * self.cfg.line_spacing + following;
+                if self.current_y - needed < self.min_y() + reserved && self.current_y < self.body_top {
+                    self.new_page();
+                }
+            }
+
+            let needed = self.min_height(section) * self.cfg.line_spacing;
+            if self.current_y - needed < self.min_y() + reserved && self.current_y < self.body_top {
                 self.new_page();
             }
             self.current_y += delta_y;
+            self.placed_footnotes
+                .extend(new_notes.iter().map(|&(number, _)| number));
+            self.notes.extend(new_lines);
             match section {
                 Section::Plain(spans) => {
                     self.current_page
-                        .render_spans(self.resources, &spans, start_x, self.current_y)
+                        .render_spans(self.resources, spans, start_x, self.current_y);
+                }
+                Section::Heading(ref heading, ref lines) => {
+                    self.current_y -= delta_y;
+                    self.headings.push(HeadingPosition {
+                        heading: heading.clone(),
+                        page: self.pages.len(),
+                        y: self.current_y,
+                    });
+                    self.render_sections(lines, start_x);
                 }
+                Section::Paragraph(_lines) => {}
                 Section::VerticalSpace(_) => {}
                 Section::ThematicBreak => {
                     let r = Span::rect(self.cfg.page_size.0 - self.cfg.margin.0 - start_x, Mm(1.0));
@@ -53,12 +517,15 @@ impl<'res> Pages<'res> {
                         .render_spans(self.resources, &[r], start_x, self.current_y);
                 }
                 Section::PageBreak => self.new_page(),
-                Section::ListItem(ref sections) => {
-                    let list_x = start_x + self.cfg.list_indentation;
-                    let list_point_x = list_x - self.cfg.list_point_offset;
+                Section::ListItem(ref marker, indentation, ref sections) => {
+                    // Markers are right aligned, so that the text of every item
+                    // starts at the same place no matter how wide its marker is
+                    let list_x = start_x + *indentation;
+                    let list_point_x =
+                        list_x - self.cfg.list_marker_spacing - marker.width(self.resources);
                     self.current_page.render_spans(
                         self.resources,
-                        &[Span::text("o".into(), [Class::Code].iter().into())],
+                        std::slice::from_ref(marker),
                         list_point_x,
                         self.current_y,
                     );
@@ -66,29 +533,496 @@ impl<'res> Pages<'res> {
                     self.render_sections(sections, list_x);
                 }
                 Section::BlockQuote(ref sections) => {
-                    self.current_page.render_spans(
-                        self.resources,
-                        &[Span::text("|".into(), [Class::Code].iter().into())],
-                        start_x,
-                        self.current_y,
-                    );
                     self.current_y -= delta_y;
-                    let quote_indentation = self.cfg.quote_indentation;
-                    self.render_sections(sections, start_x + quote_indentation);
+                    self.render_block_quote(sections, start_x);
                 }
-                Section::CodeBlock(ref lines) => {
+                Section::CodeBlock(_lines, _highlighted) => {}
+                Section::Table(ref table) => {
                     self.current_y -= delta_y;
-                    let sections: Vec<Section> =
-                        lines.iter().map(|x| Section::Plain(x.to_vec())).collect();
-                    let code_x = start_x + self.cfg.code_indentation;
-                    self.render_sections(&sections, code_x);
+                    self.render_table(table, start_x);
+                }
+                Section::TableOfContents => {}
+                Section::Footnote(_number, _lines) => {}
+                Section::TocEntry(ref entry) => {
+                    self.current_y -= delta_y;
+                    let entry_x = start_x + entry.indentation;
+                    self.render_sections(&entry.lines, entry_x);
+                    if let Some(page) = entry.page {
+                        self.render_page_number(entry, page, entry_x);
+                    }
                 }
             }
         }
     }
 
+    fn render_table(&mut self, table: &Table, start_x: Mm) {
+        let border = self.cfg.table_border_width;
+        let page_height = self.body_top - self.body_bottom - border;
+        let head_height = table
+            .head
+            .as_ref()
+            .map(|head| head.height(self.resources))
+            .unwrap_or(Mm(0.0));
+
+        // Don't leave the head of the table alone at the bottom of a page
+        let first_row_height = table
+            .rows
+            .first()
+            .map(|row| {
+                let height = row.height(self.resources);
+                if height + head_height > page_height {
+                    row.min_height(self.resources)
+                } else {
+                    height
+                }
+            })
+            .unwrap_or(Mm(0.0));
+        let reserved = self.new_notes_height(
+            table.head.iter().chain(table.rows.first()).flat_map(row_spans),
+        );
+        let needed = head_height + first_row_height + border;
+        if self.current_y - needed < self.min_y() + reserved && self.current_y < self.body_top {
+            self.new_page();
+        }
+        if let Some(ref head) = table.head {
+            self.render_table_row(table, head, start_x);
+        }
+
+        let mut rows_on_page = 0;
+        for row in table.rows.iter() {
+            let mut row = row.clone();
+            loop {
+                let row_height = row.height(self.resources);
+                let reserved = self.new_notes_height(row_spans(&row));
+                if self.current_y - row_height - border >= self.min_y() + reserved {
+                    self.render_table_row(table, &row, start_x);
+                    rows_on_page += 1;
+                    break;
+                }
+
+                // Rows are only split when they wouldn't fit on a page of their own
+                let (first, rest) = if row_height + head_height > page_height {
+                    row.split(self.resources, self.current_y - border - self.min_y() - reserved)
+                } else {
+                    (None, row.clone())
+                };
+                match first {
+                    Some(first) => self.render_table_row(table, &first, start_x),
+                    None if rows_on_page == 0 => {
+                        // Not even a single line fits on an empty page
+                        self.render_table_row(table, &rest, start_x);
+                        break;
+                    }
+                    None => {}
+                }
+
+                self.render_table_bottom(table, start_x);
+                self.new_page();
+                if let Some(ref head) = table.head {
+                    self.render_table_row(table, head, start_x);
+                }
+                rows_on_page = 0;
+                row = rest;
+            }
+        }
+
+        self.render_table_bottom(table, start_x);
+    }
+
+    /// Closes off the bottom of the last row that was rendered
+    fn render_table_bottom(&mut self, table: &Table, start_x: Mm) {
+        let border = self.cfg.table_border_width;
+        let rule = Span::rect(table.width(self.resources), border);
+        self.current_page
+            .render_spans(self.resources, &[rule], start_x, self.current_y - border);
+        self.current_y -= border;
+    }
+
+    fn render_table_row(&mut self, table: &Table, row: &TableRow, start_x: Mm) {
+        let border = self.cfg.table_border_width;
+        let padding = self.cfg.table_cell_padding;
+        let row_height = row.height(self.resources);
+        let top_y = self.current_y;
+
+        let notes = self.referenced_notes(row_spans(row));
+        self.placed_footnotes
+            .extend(notes.iter().map(|&(number, _)| number));
+        self.notes
+            .extend(notes.into_iter().flat_map(|(_, lines)| lines));
+
+        let rule = Span::rect(table.width(self.resources), border);
+        self.current_page
+            .render_spans(self.resources, &[rule], start_x, top_y - border);
+
+        let mut x = start_x;
+        for (column, lines) in table.columns.iter().zip(row.cells.iter()) {
+            let separator = Span::rect(border, row_height);
+            self.current_page
+                .render_spans(self.resources, &[separator], x, top_y - row_height);
+
+            let text_x = x + border + padding;
+            let mut y = top_y - border - padding;
+            for line in lines {
+                y -= line_height(self.resources, line) * self.cfg.line_spacing;
+                let offset = match column.alignment {
+                    Alignment::Right => column.width - line_width(self.resources, line),
+                    Alignment::Center => (column.width - line_width(self.resources, line)) / 2.0,
+                    Alignment::Left | Alignment::None => Mm(0.0),
+                };
+                self.current_page
+                    .render_spans(self.resources, line, text_x + offset, y);
+            }
+
+            x += border + column.width + padding * 2.0;
+        }
+        let separator = Span::rect(border, row_height);
+        self.current_page
+            .render_spans(self.resources, &[separator], x, top_y - row_height);
+
+        self.current_y = top_y - row_height;
+    }
+
+    /// Puts the page number of a table of contents entry at the right margin,
+    /// next to the last line of the entry, and fills the gap with dots
+    fn render_page_number(&mut self, entry: &TocEntry, page: usize, entry_x: Mm) {
+        let number = Span::text(page.to_string(), entry.style.clone());
+        let number_x = self.cfg.page_size.0 - self.cfg.margin.0 - number.width(self.resources);
+
+        let text_end = match entry.lines.last() {
+            Some(Section::Plain(spans)) => entry_x + line_width(self.resources, spans),
+            _ => entry_x,
+        };
+        let dot_width: Mm = width_of_text(self.resources, &entry.style, ".").into();
+        let leader_width = number_x - text_end - dot_width * 2.0;
+        let dots = (leader_width.0 / dot_width.0).floor();
+        if dots > 0.0 {
+            let leader = Span::text(".".repeat(dots as usize), entry.style.clone());
+            self.current_page.render_spans(
+                self.resources,
+                &[leader],
+                number_x - dot_width * (dots + 1.0),
+                self.current_y,
+            );
+        }
+        self.current_page
+            .render_spans(self.resources, &[number], number_x, self.current_y);
+    }
+
+    /// The headings that have been rendered so far, in document order
+    pub fn headings(&self) -> &[HeadingPosition] {
+        &self.headings
+    }
+
+    /// The text of the heading that a page belongs to: the first level one or
+    /// two heading that starts on the page, or else the last one before it
+    fn section_of_page(&self, page: usize) -> &str {
+        let sections = self.headings.iter().filter(|p| p.heading.level <= 2);
+        sections
+            .clone()
+            .find(|p| p.page == page)
+            .or_else(|| sections.rev().find(|p| p.page < page))
+            .map(|p| p.heading.text.as_str())
+            .unwrap_or("")
+    }
+
+    /// Puts the header and the footer on every page
+    fn render_templates(&mut self) {
+        let (ascent, descent) = font_ascent_descent(self.resources, &Default::default());
+        let header_y = self.cfg.page_size.1 - self.cfg.margin.1 - ascent.into();
+        let footer_y = self.cfg.margin.1 - descent.into();
+
+        let pages = self.pages.len();
+        for idx in 0..pages {
+            let section = self.section_of_page(idx).to_string();
+            let values = TemplateValues {
+                page: idx + 1,
+                pages,
+                title: &self.cfg.title,
+                section: &section,
+            };
+            let page = &mut self.pages[idx];
+            self.cfg.header.render(self.resources, page, header_y, &values);
+            self.cfg.footer.render(self.resources, page, footer_y, &values);
+        }
+    }
+
     pub fn into_vec(mut self) -> Vec<Page> {
-        self.pages.push(self.current_page);
+        // Footnotes that don't fit onto the last page get pages of their own
+        self.render_notes();
+        while !self.notes.is_empty() {
+            self.next_page();
+            self.render_notes();
+        }
+        let last_page = self.current_page.clone();
+        self.pages.push(last_page);
+        self.render_templates();
         self.pages
     }
 }
+
+/// The spans that a section puts on the page itself, as opposed to the spans of
+/// the sections inside of it
+fn section_spans(section: &Section) -> Vec<&Span> {
+    match section {
+        Section::Plain(spans) => spans.iter().collect(),
+        Section::Heading(_heading, lines) => lines.iter().flat_map(section_spans).collect(),
+        _ => vec![],
+    }
+}
+
+/// The spans of all cells of a table row
+fn row_spans(row: &TableRow) -> impl Iterator<Item = &Span> {
+    row.cells.iter().flatten().flatten()
+}
+
+/// How far the text of `line` reaches below its baseline, as a negative distance
+fn line_descent(resources: &Resources, line: &[Span]) -> Mm {
+    line.iter()
+        .filter_map(|span| match span {
+            Span::Text { style, .. } => Some(font_ascent_descent(resources, style).1),
+            _ => None,
+        })
+        .fold(Mm(0.0), |lowest, descent| {
+            let descent: Mm = descent.into();
+            if descent < lowest {
+                descent
+            } else {
+                lowest
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heading::Heading;
+    use crate::table::Column;
+
+    /// Renders the sections that `sections` makes for a page body of the given
+    /// height, and returns the text that ends up on each page
+    fn text_by_page<F: Fn(&Resources, Mm) -> Vec<Section>>(sections: F) -> Vec<Vec<String>> {
+        let cfg = Config::default();
+        let resources = Resources::new(cfg.clone());
+        let mut pages = Pages::new(&cfg, &resources);
+        let body_height = pages.body_top - pages.body_bottom;
+        pages.render_sections(&sections(&resources, body_height), cfg.margin.0);
+        pages
+            .into_vec()
+            .into_iter()
+            .map(|page| {
+                page.into_vec()
+                    .into_iter()
+                    .filter_map(|span| match span.span {
+                        Span::Text { text, .. } => Some(text),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn line(text: &str) -> Section {
+        Section::plain(vec![Span::text(text.into(), Default::default())])
+    }
+
+    fn line_height(resources: &Resources) -> Mm {
+        line("x").height(resources)
+    }
+
+    /// Empty space that leaves room for `lines` lines at the bottom of the page
+    fn leaving(resources: &Resources, body_height: Mm, lines: f64) -> Section {
+        Section::space(body_height - line_height(resources) * lines)
+    }
+
+    fn heading(text: &str) -> Section {
+        let heading = Heading {
+            level: 1,
+            text: text.into(),
+            anchor: text.to_lowercase(),
+        };
+        Section::heading(heading, vec![line(text)])
+    }
+
+    fn paragraph(lines: &[&str]) -> Section {
+        Section::paragraph(lines.iter().map(|text| line(text)).collect())
+    }
+
+    fn texts(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_headings_with_the_lines_after_them() {
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 2.5),
+                heading("Heading"),
+                paragraph(&["one", "two", "three"]),
+            ]
+        });
+        assert_eq!(pages, vec![vec![], texts(&["Heading", "one", "two", "three"])]);
+    }
+
+    #[test]
+    fn leaves_headings_that_fit_on_the_page() {
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 3.5),
+                heading("Heading"),
+                paragraph(&["one", "two", "three", "four", "five"]),
+            ]
+        });
+        assert_eq!(
+            pages,
+            vec![texts(&["Heading", "one", "two"]), texts(&["three", "four", "five"])]
+        );
+    }
+
+    #[test]
+    fn renders_sections_taller_than_the_page() {
+        let cfg = Config::default();
+        let resources = Resources::new(cfg.clone());
+        let mut pages = Pages::new(&cfg, &resources);
+        let tall = Span::rect(Mm(10.0), cfg.page_size.1 * 2.0);
+        pages.render_sections(&[Section::plain(vec![tall])], cfg.margin.0);
+        assert!(!pages.into_vec().is_empty());
+    }
+
+    #[test]
+    fn keeps_headings_with_paragraphs_that_cant_be_broken() {
+        // Two lines would fit after the heading, but the third one can't be
+        // left alone on the next page
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 3.5),
+                heading("Heading"),
+                paragraph(&["one", "two", "three"]),
+            ]
+        });
+        assert_eq!(pages, vec![vec![], texts(&["Heading", "one", "two", "three"])]);
+    }
+
+    #[test]
+    fn breaks_paragraphs_between_their_lines() {
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 3.5),
+                paragraph(&["one", "two", "three", "four", "five"]),
+            ]
+        });
+        assert_eq!(
+            pages,
+            vec![texts(&["one", "two", "three"]), texts(&["four", "five"])]
+        );
+    }
+
+    #[test]
+    fn doesnt_leave_orphans() {
+        // Only the first line would fit on the first page
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 1.5),
+                paragraph(&["one", "two", "three", "four", "five"]),
+            ]
+        });
+        assert_eq!(pages, vec![vec![], texts(&["one", "two", "three", "four", "five"])]);
+    }
+
+    #[test]
+    fn doesnt_leave_widows() {
+        // Only the last line would be carried over to the next page
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 4.5),
+                paragraph(&["one", "two", "three", "four", "five"]),
+            ]
+        });
+        assert_eq!(
+            pages,
+            vec![texts(&["one", "two", "three"]), texts(&["four", "five"])]
+        );
+    }
+
+    #[test]
+    fn moves_short_paragraphs_as_a_whole() {
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving(resources, body_height, 2.5),
+                paragraph(&["one", "two", "three"]),
+            ]
+        });
+        assert_eq!(pages, vec![vec![], texts(&["one", "two", "three"])]);
+    }
+
+    /// A table of one column, with a row for each text
+    fn table(head: &str, rows: &[&str]) -> Table {
+        let row = |text: &str| TableRow {
+            cells: vec![vec![vec![Span::text(text.into(), Default::default())]]],
+        };
+        Table {
+            columns: vec![Column {
+                width: Mm(20.0),
+                alignment: Alignment::None,
+            }],
+            head: Some(row(head)),
+            rows: rows.iter().map(|text| row(text)).collect(),
+        }
+    }
+
+    /// Empty space that leaves room for `rows` rows of `table` at the bottom of
+    /// the page
+    fn leaving_rows(resources: &Resources, body_height: Mm, table: &Table, rows: f64) -> Section {
+        let row_height = table.rows[0].height(resources);
+        let border = resources.get_config().table_border_width;
+        Section::space(body_height - row_height * rows - border)
+    }
+
+    #[test]
+    fn repeats_the_head_of_tables_split_across_pages() {
+        let table = table("head", &["one", "two", "three", "four"]);
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving_rows(resources, body_height, &table, 3.5),
+                Section::table(table.clone()),
+            ]
+        });
+        assert_eq!(
+            pages,
+            vec![texts(&["head", "one", "two"]), texts(&["head", "three", "four"])]
+        );
+    }
+
+    #[test]
+    fn keeps_the_head_of_tables_with_their_first_row() {
+        let table = table("head", &["one", "two"]);
+        let pages = text_by_page(|resources, body_height| {
+            vec![
+                leaving_rows(resources, body_height, &table, 1.5),
+                Section::table(table.clone()),
+            ]
+        });
+        assert_eq!(pages, vec![vec![], texts(&["head", "one", "two"])]);
+    }
+
+    #[test]
+    fn starts_tables_taller_than_the_page_on_the_current_page() {
+        let cfg = Config::default();
+        let resources = Resources::new(cfg.clone());
+        let mut pages = Pages::new(&cfg, &resources);
+        let cell = |height| vec![vec![Span::rect(Mm(10.0), height)]];
+        let table = Table {
+            columns: vec![Column {
+                width: Mm(10.0),
+                alignment: Alignment::None,
+            }],
+            head: Some(TableRow {
+                cells: vec![cell(cfg.page_size.1)],
+            }),
+            rows: vec![TableRow {
+                cells: vec![cell(Mm(10.0))],
+            }],
+        };
+        pages.render_sections(&[Section::table(table)], cfg.margin.0);
+        assert!(pages.into_vec()[0].span_count() > 0);
+    }
+}
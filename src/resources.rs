@@ -17,10 +17,16 @@ pub struct Resources {
     config: Config,
 }
 
+/// Reads the files that a document refers to. Paths are relative to
+/// `Config::resources_directory`.
 pub trait Loader {
     fn queue_font(&mut self, path: &str);
     fn queue_image(&mut self, path: &str);
     fn load_resources(&self, res: &mut Resources) -> Vec<Error>;
+    /// Reads a text file, like the source code that is included into a code
+    /// block. `SimpleLoader` refuses paths that lead out of the resources
+    /// directory.
+    fn load_text(&self, path: &str) -> Result<String, Error>;
 }
 
 pub struct SimpleLoader {
@@ -42,7 +48,7 @@ impl Resources {
             root_path: config.resources_directory.clone(),
             images: BTreeMap::new(),
             fonts: BTreeMap::new(),
-            config: config,
+            config,
         };
         res.fonts.insert(
             DEFAULT_REGULAR_FONT.into(),
@@ -83,7 +89,7 @@ impl Resources {
         self.fonts.insert(filename, font);
     }
 
-    pub fn get_font(&self, path: &str) -> Option<&Font> {
+    pub fn get_font(&self, path: &str) -> Option<&Font<'_>> {
         let filename = self.root_path.join(path);
         self.fonts.get(&filename)
     }
@@ -100,7 +106,7 @@ impl Resources {
 impl SimpleLoader {
     pub fn new(root_path: PathBuf) -> Self {
         Self {
-            root_path: root_path,
+            root_path,
             queued_images: HashSet::new(),
             queued_fonts: HashSet::new(),
         }
@@ -150,4 +156,41 @@ impl Loader for SimpleLoader {
         }
         errors
     }
+
+    fn load_text(&self, path: &str) -> Result<String, Error> {
+        // Documents only get to read the files in their resources directory,
+        // and not things like `/etc/passwd` or `../../.ssh/id_rsa`
+        let root = PathBuf::from(".").join(&self.root_path).canonicalize()?;
+        let filename = root.join(path).canonicalize()?;
+        if !filename.starts_with(&root) {
+            return Err(format_err!(
+                "{:?} is outside of the resources directory {:?}",
+                path,
+                root
+            ));
+        }
+
+        let text = std::fs::read_to_string(&filename)?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_text_inside_of_the_root() {
+        let loader = SimpleLoader::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src"));
+        assert!(loader.load_text("lib.rs").is_ok());
+        assert!(loader.load_text("./bin/../lib.rs").is_ok());
+        assert!(loader.load_text("missing.rs").is_err());
+    }
+
+    #[test]
+    fn refuses_text_outside_of_the_root() {
+        let loader = SimpleLoader::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src"));
+        assert!(loader.load_text("../Cargo.toml").is_err());
+        assert!(loader.load_text(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).is_err());
+    }
 }
@@ -1,16 +1,35 @@
+use crate::heading::Heading;
 use printpdf::Mm;
 use crate::resources::Resources;
 use crate::span::Span;
+use crate::table::Table;
+use crate::toc::TocEntry;
 
 #[derive(Clone, Debug)]
 pub enum Section {
     Plain(Vec<Span>),
+    /// The lines of a paragraph, kept together so that pagination can avoid
+    /// leaving a single line of it at the top or bottom of a page
+    Paragraph(Vec<Section>),
+    /// A heading, along with the lines it was broken into
+    Heading(Heading, Vec<Section>),
     VerticalSpace(Mm),
     ThematicBreak,
     PageBreak,
-    ListItem(Vec<Section>),
+    /// A list item, along with the marker (bullet or number) drawn beside it
+    /// and the indentation of the item's text
+    ListItem(Span, Mm, Vec<Section>),
     BlockQuote(Vec<Section>),
-    CodeBlock(Vec<Vec<Span>>),
+    /// The lines of a code block, along with the indices of the lines that are
+    /// highlighted
+    CodeBlock(Vec<Vec<Span>>, Vec<usize>),
+    Table(Table),
+    /// Where the table of contents goes, before it has been generated
+    TableOfContents,
+    TocEntry(TocEntry),
+    /// The definition of a footnote. It takes up no space where it is defined,
+    /// but at the bottom of the page that references it.
+    Footnote(usize, Vec<Section>),
 }
 
 impl Section {
@@ -18,20 +37,40 @@ impl Section {
         Section::Plain(spans)
     }
 
+    pub fn paragraph(lines: Vec<Section>) -> Self {
+        Section::Paragraph(lines)
+    }
+
+    pub fn heading(heading: Heading, lines: Vec<Section>) -> Self {
+        Section::Heading(heading, lines)
+    }
+
     pub fn space(height: Mm) -> Self {
         Section::VerticalSpace(height)
     }
 
-    pub fn list_item(sections: Vec<Section>) -> Self {
-        Section::ListItem(sections)
+    pub fn list_item(marker: Span, indentation: Mm, sections: Vec<Section>) -> Self {
+        Section::ListItem(marker, indentation, sections)
     }
 
     pub fn block_quote(sections: Vec<Section>) -> Self {
         Section::BlockQuote(sections)
     }
 
-    pub fn code_block(lines: Vec<Vec<Span>>) -> Self {
-        Section::CodeBlock(lines)
+    pub fn code_block(lines: Vec<Vec<Span>>, highlighted: Vec<usize>) -> Self {
+        Section::CodeBlock(lines, highlighted)
+    }
+
+    pub fn table(table: Table) -> Self {
+        Section::Table(table)
+    }
+
+    pub fn toc_entry(entry: TocEntry) -> Self {
+        Section::TocEntry(entry)
+    }
+
+    pub fn footnote(number: usize, lines: Vec<Section>) -> Self {
+        Section::Footnote(number, lines)
     }
 
     pub fn page_break() -> Self {
@@ -44,12 +83,14 @@ impl Section {
                 .iter()
                 .map(|x| x.height(resources).0)
                 .fold(0.0, |x, acc| acc.max(x)),
+            Section::Paragraph(lines) => lines.iter().map(|x| x.height(resources).0).sum(),
+            Section::Heading(_heading, lines) => lines.iter().map(|x| x.height(resources).0).sum(),
             Section::VerticalSpace(space_pt) => space_pt.0,
             Section::ThematicBreak => 0.0,
             Section::PageBreak => 0.0,
-            Section::ListItem(sections) => sections.iter().map(|x| x.height(resources).0).sum(),
+            Section::ListItem(_marker, _indentation, sections) => sections.iter().map(|x| x.height(resources).0).sum(),
             Section::BlockQuote(sections) => sections.iter().map(|x| x.height(resources).0).sum(),
-            Section::CodeBlock(lines) => lines
+            Section::CodeBlock(lines, _) => lines
                 .iter()
                 .map(|line| {
                     line.iter()
@@ -57,6 +98,10 @@ impl Section {
                         .fold(0.0, |x, acc| acc.max(x))
                 })
                 .sum(),
+            Section::Table(table) => table.height(resources).0,
+            Section::TableOfContents => 0.0,
+            Section::TocEntry(entry) => entry.lines.iter().map(|x| x.height(resources).0).sum(),
+            Section::Footnote(_number, _lines) => 0.0,
         };
         Mm(r)
     }
@@ -64,21 +109,39 @@ impl Section {
     pub fn min_step(&self, resources: &Resources) -> Mm {
         let r = match self {
             Section::Plain(_) => self.height(resources).0,
+            Section::Paragraph(lines) => {
+                lines.iter().take(1).map(|x| x.height(resources).0).sum()
+            }
+            Section::Heading(_heading, lines) => {
+                lines.iter().take(1).map(|x| x.height(resources).0).sum()
+            }
             Section::VerticalSpace(_) => self.height(resources).0,
             Section::ThematicBreak => self.height(resources).0,
             Section::PageBreak => self.height(resources).0,
-            Section::ListItem(sections) => {
-                sections.iter().take(1).map(|x| x.height(resources).0).sum()
+            Section::ListItem(_marker, _indentation, sections) => {
+                sections.iter().take(1).map(|x| x.min_step(resources).0).sum()
             }
             Section::BlockQuote(sections) => {
-                sections.iter().take(1).map(|x| x.height(resources).0).sum()
+                sections.iter().take(1).map(|x| x.min_step(resources).0).sum()
             }
-            Section::CodeBlock(lines) => lines
+            Section::CodeBlock(lines, _) => lines
                 .iter()
                 .take(1)
                 .flat_map(|x| x.iter())
                 .map(|x| x.height(resources).0)
                 .sum(),
+            Section::Table(table) => table
+                .head
+                .iter()
+                .chain(table.rows.iter())
+                .take(1)
+                .map(|x| x.height(resources).0)
+                .sum(),
+            Section::TableOfContents => 0.0,
+            Section::TocEntry(entry) => {
+                entry.lines.iter().take(1).map(|x| x.height(resources).0).sum()
+            }
+            Section::Footnote(_number, _lines) => 0.0,
         };
         Mm(r)
     }
@@ -88,10 +151,16 @@ impl Section {
             Section::PageBreak => true,
             Section::VerticalSpace(_) => true,
             Section::ThematicBreak => false,
+            Section::Heading(_heading, _lines) => false,
             Section::Plain(spans) => spans.len() == 0,
-            Section::ListItem(_sections) => false,
+            Section::Paragraph(_lines) => false,
+            Section::ListItem(_marker, _indentation, _sections) => false,
             Section::BlockQuote(_sections) => false,
-            Section::CodeBlock(_lines) => false,
+            Section::CodeBlock(_lines, _highlighted) => false,
+            Section::Table(_table) => false,
+            Section::TableOfContents => false,
+            Section::TocEntry(_entry) => false,
+            Section::Footnote(_number, _lines) => false,
         }
     }
 }
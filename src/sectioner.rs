@@ -1,16 +1,23 @@
+use crate::alignment::{align_line, TextAlignment};
+use crate::code::{shrink_code_block, wrap_code_line, CodeBlockInfo, CodeOverflow};
 use crate::atomizer::{Atom, BlockTag, Break};
+use crate::heading::Heading;
+use crate::hyphenation::syllables;
+use crate::linebreak::{break_lines, LineBreaking};
 use printpdf::Mm;
 use crate::resources::Resources;
 use crate::section::Section;
 use crate::sizer::{SizedAtom, SizedEvent};
 use crate::span::Span;
-use crate::style::Style;
-use crate::util::width_of_text;
+use crate::style::{Class, Style};
+use crate::table::TableBuilder;
+use crate::util::{overlong_split, width_of_text};
 use crate::Config;
 
 pub enum SubsectionType {
     List,
     Quote,
+    Footnote(usize),
 }
 
 pub struct Sectioner<'res> {
@@ -21,6 +28,22 @@ pub struct Sectioner<'res> {
     min_x: Mm,
     max_x: Mm,
     subsection: Option<Box<Sectioner<'res>>>,
+    table: Option<TableBuilder>,
+    /// The heading that is being written, and the index of its first line
+    heading: Option<(Heading, usize)>,
+    /// How many list items this sectioner is nested inside of
+    list_depth: usize,
+    /// The number of the next item of the current list, if it is ordered
+    list_number: Option<usize>,
+    /// The indentation of the items of the current list
+    list_indentation: Mm,
+    /// Whether the list item this sectioner is writing is a done task, or
+    /// `None` if the item isn't a task
+    task: Option<bool>,
+    /// The style of the spaces between words
+    space_style: Style,
+    /// The alignment of the lines written by this sectioner, apart from headings
+    pub alignment: TextAlignment,
     is_code: bool,
     is_alt_text: bool,
     resources: &'res Resources,
@@ -34,30 +57,44 @@ impl<'res> Sectioner<'res> {
             lines: Vec::new(),
             current_line: Vec::new(),
             current_code_block: Vec::new(),
-            min_x: min_x,
-            max_x: max_x,
+            min_x,
+            max_x,
             subsection: None,
+            table: None,
+            heading: None,
+            list_depth: 0,
+            list_number: None,
+            list_indentation: resources.get_config().list_indentation,
+            task: None,
+            space_style: Style::default(),
+            alignment: resources.get_config().text_alignment,
             is_code: false,
             is_alt_text: false,
-            resources: resources,
+            resources,
             cfg: resources.get_config(),
         }
     }
 
-    pub fn parse_event(
-        &mut self,
-        resources: &Resources,
-        event: SizedEvent,
-    ) -> Option<SubsectionType> {
+    pub fn parse_event(&mut self, event: SizedEvent) -> Option<SubsectionType> {
         if self.subsection.is_some() {
             let mut subsection = self
                 .subsection
                 .take()
                 .expect("Checked if the subsection was `Some`");
-            if let Some(sub_type) = subsection.parse_event(resources, event) {
+            if let Some(sub_type) = subsection.parse_event(event) {
                 let section = match sub_type {
-                    SubsectionType::List => Section::list_item(subsection.get_vec()),
+                    SubsectionType::List => {
+                        let marker = match subsection.task {
+                            Some(checked) => self.task_marker(checked),
+                            None => self.list_marker(),
+                        };
+                        self.list_number = self.list_number.map(|n| n + 1);
+                        Section::list_item(marker, self.list_indentation, subsection.get_vec())
+                    }
                     SubsectionType::Quote => Section::block_quote(subsection.get_vec()),
+                    SubsectionType::Footnote(number) => {
+                        Section::footnote(number, subsection.get_vec())
+                    }
                 };
                 self.push_section(section);
             } else {
@@ -65,39 +102,146 @@ impl<'res> Sectioner<'res> {
             };
             return None;
         }
+        if let Some(mut table) = self.table.take() {
+            if let SizedEvent::EndBlock(BlockTag::Table(_)) = event {
+                let table = table.build(self.resources, self.max_x - self.min_x);
+                self.push_section(Section::table(table));
+                self.push_space();
+            } else {
+                table.parse_event(event);
+                self.table = Some(table);
+            }
+            return None;
+        }
         match event {
             SizedEvent::Break(Break::HorizontalRule) => self.push_section(Section::ThematicBreak),
 
-            SizedEvent::StartBlock(BlockTag::List(_)) => self.new_line(),
-            SizedEvent::EndBlock(BlockTag::List(_)) => self.push_space(),
+            SizedEvent::StartBlock(BlockTag::Heading(heading)) => {
+                self.new_line();
+                // Headings at the start of a block don't need to be separated
+                // from anything
+                if !self.lines.is_empty() {
+                    let mut space = heading_spacing(&self.cfg.heading_space_before, heading.level);
+                    if let Some(Section::VerticalSpace(previous)) = self.lines.last() {
+                        if *previous > space {
+                            space = *previous;
+                        }
+                        self.lines.pop();
+                    }
+                    self.push_section(Section::space(space));
+                }
+                self.heading = Some((heading, self.lines.len()));
+            }
+            SizedEvent::EndBlock(BlockTag::Heading(heading)) => {
+                self.new_line();
+                if let Some((heading, start)) = self.heading.take() {
+                    let lines = self.lines.split_off(start);
+                    self.push_section(Section::heading(heading, lines));
+                }
+                let space = heading_spacing(&self.cfg.heading_space_after, heading.level);
+                self.push_section(Section::space(space));
+            }
+
+            SizedEvent::StartBlock(BlockTag::List(first_number, items)) => {
+                self.new_line();
+                self.list_number = first_number;
+
+                // Make room for the widest marker of the list, so that lists
+                // with long numbers like `10.` or `VIII.` still line up
+                let marker_width = match first_number {
+                    Some(first) => (first..first + items.max(1))
+                        .map(|number| self.numbered_marker(number).width(self.resources))
+                        .fold(Mm(0.0), |widest, width| if width > widest { width } else { widest }),
+                    None => self.list_marker().width(self.resources),
+                };
+                let indentation = marker_width + self.cfg.list_marker_spacing;
+                self.list_indentation = if indentation > self.cfg.list_indentation {
+                    indentation
+                } else {
+                    self.cfg.list_indentation
+                };
+            }
+            SizedEvent::EndBlock(BlockTag::List(_, _)) => {
+                self.list_number = None;
+                self.push_space();
+            }
 
             SizedEvent::StartBlock(BlockTag::ListItem) => {
-                self.subsection = Some(Box::new(Sectioner::new(
-                    self.min_x + self.cfg.list_indentation,
+                let mut subsection = Sectioner::new(
+                    self.min_x + self.list_indentation,
                     self.max_x,
-                    &self.resources,
-                )))
+                    self.resources,
+                );
+                subsection.list_depth = self.list_depth + 1;
+                subsection.alignment = self.alignment;
+                self.subsection = Some(Box::new(subsection));
             }
             SizedEvent::EndBlock(BlockTag::ListItem) => return Some(SubsectionType::List),
 
             SizedEvent::StartBlock(BlockTag::BlockQuote) => {
                 self.new_line();
-                self.subsection = Some(Box::new(Sectioner::new(
+                let mut subsection = Sectioner::new(
                     self.min_x + self.cfg.quote_indentation,
                     self.max_x,
-                    &self.resources,
-                )))
+                    self.resources,
+                );
+                subsection.list_depth = self.list_depth;
+                subsection.alignment = self.cfg.quote_alignment.unwrap_or(self.alignment);
+                self.subsection = Some(Box::new(subsection));
             }
             SizedEvent::EndBlock(BlockTag::BlockQuote) => return Some(SubsectionType::Quote),
 
+            SizedEvent::StartBlock(BlockTag::Footnote(number)) => {
+                // Footnotes are put at the bottom of the page, so they always
+                // take up the full width of the page
+                self.new_line();
+                let mut subsection = Sectioner::new(
+                    self.cfg.margin.0,
+                    self.cfg.page_size.0 - self.cfg.margin.0,
+                    self.resources,
+                );
+                let marker_style: Style = [Class::Note, Class::Superscript].iter().into();
+                subsection.space_style = [Class::Note].iter().into();
+                subsection.alignment = self.cfg.footnote_alignment.unwrap_or(self.cfg.text_alignment);
+                subsection.write(&number.to_string(), &marker_style);
+                subsection.write(" ", &subsection.space_style.clone());
+                self.subsection = Some(Box::new(subsection));
+            }
+            SizedEvent::EndBlock(BlockTag::Footnote(number)) => {
+                return Some(SubsectionType::Footnote(number))
+            }
+
+            SizedEvent::StartBlock(BlockTag::Table(alignments)) => {
+                self.new_line();
+                self.table = Some(TableBuilder::new(alignments));
+            }
+            SizedEvent::StartBlock(BlockTag::TableHead)
+            | SizedEvent::EndBlock(BlockTag::TableHead)
+            | SizedEvent::StartBlock(BlockTag::TableRow)
+            | SizedEvent::EndBlock(BlockTag::TableRow)
+            | SizedEvent::StartBlock(BlockTag::TableCell)
+            | SizedEvent::EndBlock(BlockTag::TableCell)
+            | SizedEvent::EndBlock(BlockTag::Table(_)) => {
+                warn!("Table element found outside of a table: {:?}", event)
+            }
+
             SizedEvent::Break(Break::Word) => {
                 if self.x > self.min_x {
-                    self.write(" ", &Style::default());
+                    let style = self.space_style.clone();
+                    self.write(" ", &style);
                 }
             }
 
             SizedEvent::Break(Break::Page) => self.push_section(Section::page_break()),
 
+            SizedEvent::TaskListMarker(checked) => self.task = Some(checked),
+
+            SizedEvent::TableOfContents => {
+                self.new_line();
+                self.push_section(Section::TableOfContents);
+                self.push_space();
+            }
+
             SizedEvent::SizedAtom(SizedAtom {
                 atom,
                 width,
@@ -105,24 +249,24 @@ impl<'res> Sectioner<'res> {
             }) => {
                 match atom {
                     Atom::Text { text, style } => {
-                        self.write_left_aligned(&text, &style);
+                        self.write_wrapped(&text, &style);
                     }
 
-                    Atom::Image { uri } => {
-                        let span = Span::image(width, height, uri.into_owned().into());
+                    Atom::Image { uri, link } => {
+                        let span = Span::image(width, height, uri.into_owned().into(), link);
                         self.push_span(span);
                         self.is_alt_text = true;
                     }
                 };
             }
 
-            SizedEvent::StartBlock(BlockTag::CodeBlock) => {
+            SizedEvent::StartBlock(BlockTag::CodeBlock(_)) => {
                 self.is_code = true;
             }
-            SizedEvent::EndBlock(BlockTag::CodeBlock) => {
-                let code_block = Section::code_block(self.current_code_block.clone());
-                self.push_section(code_block);
-                self.current_code_block.clear();
+            SizedEvent::EndBlock(BlockTag::CodeBlock(info)) => {
+                let lines = std::mem::take(&mut self.current_code_block);
+                let (lines, highlighted) = self.fit_code_lines(lines, &info);
+                self.push_section(Section::code_block(lines, highlighted));
 
                 self.push_space();
                 self.is_code = false;
@@ -130,6 +274,7 @@ impl<'res> Sectioner<'res> {
 
             SizedEvent::Break(Break::Paragraph) => {
                 self.new_line();
+                self.end_paragraph();
                 self.push_space();
             }
 
@@ -138,6 +283,56 @@ impl<'res> Sectioner<'res> {
         None
     }
 
+    /// The marker that is put in front of the current list item
+    fn list_marker(&self) -> Span {
+        match self.list_number {
+            Some(number) => self.numbered_marker(number),
+            None => {
+                let bullets = &self.cfg.list_bullets;
+                let bullet = match bullets.len() {
+                    0 => String::new(),
+                    len => bullets[self.list_depth % len].clone(),
+                };
+                Span::text(bullet, Style::default())
+            }
+        }
+    }
+
+    /// The marker of the item with the given number of an ordered list
+    fn numbered_marker(&self, number: usize) -> Span {
+        let styles = &self.cfg.ordered_list_styles;
+        let number = match styles.len() {
+            0 => number.to_string(),
+            len => styles[self.list_depth % len].format(number),
+        };
+        Span::text(format!("{}.", number), Style::default())
+    }
+
+    /// The box that is put in front of a task list item instead of a bullet
+    fn task_marker(&self, checked: bool) -> Span {
+        let marker = if checked {
+            &self.cfg.checked_task_marker
+        } else {
+            &self.cfg.unchecked_task_marker
+        };
+        Span::text(marker.clone(), [Class::Checkbox(checked)].iter().into())
+    }
+
+    /// Groups the lines at the end of the output, which belong to the paragraph
+    /// that just ended, into a single section
+    fn end_paragraph(&mut self) {
+        let start = self
+            .lines
+            .iter()
+            .rposition(|section| !matches!(section, Section::Plain(_)))
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        if start < self.lines.len() {
+            let lines = self.lines.split_off(start);
+            self.push_section(Section::paragraph(lines));
+        }
+    }
+
     pub fn push_space(&mut self) {
         let spacing = Section::space(self.cfg.section_spacing);
         self.push_section(spacing);
@@ -147,14 +342,97 @@ impl<'res> Sectioner<'res> {
         self.lines.push(section);
     }
 
-    pub fn write_left_aligned(&mut self, text: &str, style: &Style) {
-        let width = width_of_text(self.resources, style, text).into();
-        if self.x + width > self.max_x {
-            self.new_line();
+    /// Writes `text`, starting a new line first if it doesn't fit on the
+    /// current one
+    pub fn write_wrapped(&mut self, text: &str, style: &Style) {
+        // Lines of code are only wrapped once the whole code block is known
+        if self.is_code {
+            self.write(text, style);
+            return;
         }
+        let syllables = if style.contains(&Class::Code) {
+            vec![text.to_string()]
+        } else {
+            // Headings are only hyphenated where the text asks for it
+            let hyphenator = match self.heading {
+                Some(_) => None,
+                None => self.cfg.hyphenation.as_ref(),
+            };
+            syllables(hyphenator, text)
+        };
 
-        let span = Span::text(text.to_string(), style.clone());
-        self.push_span(span);
+        // The optimal line breaker needs the whole paragraph, so its lines are
+        // only broken once the paragraph ends
+        if self.cfg.line_breaking == LineBreaking::Optimal {
+            for (idx, syllable) in syllables.iter().enumerate() {
+                let width: Mm = width_of_text(self.resources, style, syllable).into();
+                if width > self.max_x - self.min_x {
+                    // The word is broken up right away, starting on the last
+                    // of the lines before it
+                    self.break_pending_lines();
+                    self.write_overlong(syllable, style);
+                    continue;
+                }
+                if idx > 0 {
+                    self.push_span(Span::hyphen(style.clone()));
+                }
+                self.write(syllable, style);
+            }
+            return;
+        }
+
+        let mut rest = &syllables[..];
+        while !rest.is_empty() {
+            let word = rest.concat();
+            let width: Mm = width_of_text(self.resources, style, &word).into();
+            if self.x + width <= self.max_x {
+                self.write(&word, style);
+                return;
+            }
+
+            // Put as much of the word on the line as fits, followed by a hyphen
+            let fitting = (1..rest.len()).rev().find(|&n| {
+                let start = format!("{}-", rest[..n].concat());
+                let width: Mm = width_of_text(self.resources, style, &start).into();
+                self.x + width <= self.max_x
+            });
+            match fitting {
+                Some(n) => {
+                    self.write(&format!("{}-", rest[..n].concat()), style);
+                    self.end_line(true);
+                    rest = &rest[n..];
+                }
+                // Words that fit on a line of their own are moved to the next
+                // one, longer words are broken up right away
+                None if self.x > self.min_x && width <= self.max_x - self.min_x => {
+                    self.end_line(true)
+                }
+                None => {
+                    self.write_overlong(&word, style);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Writes a word that is wider than a whole line, breaking it wherever it
+    /// reaches the right margin
+    fn write_overlong(&mut self, word: &str, style: &Style) {
+        warn!(
+            "{:?} is wider than a line, so it is broken up without a hyphen",
+            word
+        );
+        let mut rest = word;
+        loop {
+            let split = overlong_split(self.resources, style, rest, self.max_x - self.x);
+            if split >= rest.len() {
+                self.write(rest, style);
+                return;
+            }
+            self.write(&rest[..split], style);
+            self.end_line(true);
+            rest = &rest[split..];
+        }
     }
 
     pub fn write(&mut self, text: &str, style: &Style) {
@@ -168,23 +446,151 @@ impl<'res> Sectioner<'res> {
     }
 
     pub fn new_line(&mut self) {
-        if self.current_line.len() == 0 {
-            return;
-        }
+        self.end_line(false);
+    }
+
+    /// Finishes the current line. `wrapped` is set when the line ends because
+    /// the next word didn't fit on it.
+    fn end_line(&mut self, wrapped: bool) {
         if self.is_code {
+            // Empty lines of code are kept, with the height of a line of code
+            if self.current_line.is_empty() {
+                let style = [Class::Code].iter().into();
+                self.current_line.push(Span::text(String::new(), style));
+            }
             self.current_code_block.push(self.current_line.clone());
+        } else if self.current_line.is_empty() {
+            return;
         } else {
-            self.lines.push(Section::plain(self.current_line.clone()));
+            let alignment = self.line_alignment();
+            let line = std::mem::take(&mut self.current_line);
+            let width = self.max_x - self.min_x;
+            let lines = match self.cfg.line_breaking {
+                LineBreaking::Greedy => vec![line],
+                LineBreaking::Optimal => break_lines(self.resources, line, width),
+            };
+            let last = lines.len() - 1;
+            for (idx, mut line) in lines.into_iter().enumerate() {
+                align_line(self.resources, &mut line, width, alignment, wrapped || idx < last);
+                self.lines.push(Section::plain(line));
+            }
         }
         self.current_line.clear();
         self.x = self.min_x;
     }
 
+    /// Makes the lines of a code block fit between the margins, by shrinking
+    /// its font or wrapping the lines that are too long, and numbers them if
+    /// asked to. Along with the lines, it returns the indices of the lines
+    /// that are highlighted.
+    fn fit_code_lines(
+        &self,
+        mut lines: Vec<Vec<Span>>,
+        info: &CodeBlockInfo,
+    ) -> (Vec<Vec<Span>>, Vec<usize>) {
+        let numbered = info.line_numbers || self.cfg.code_line_numbers;
+        let first_number = info.first_line.unwrap_or(1);
+        let last_number = first_number + lines.len().saturating_sub(1);
+        for &(start, end) in info.highlighted.iter() {
+            if end < first_number || start > last_number {
+                warn!(
+                    "Lines {}-{} can't be highlighted, the code block has lines {}-{}",
+                    start, end, first_number, last_number
+                );
+            }
+        }
+        let gutter = |style: &Style| -> Mm {
+            if numbered {
+                let digits: Mm = width_of_text(self.resources, style, &last_number.to_string()).into();
+                digits + self.cfg.code_line_number_spacing
+            } else {
+                Mm(0.0)
+            }
+        };
+
+        let mut width = self.max_x - self.min_x - self.cfg.code_indentation - self.cfg.code_padding;
+        if self.cfg.code_overflow == CodeOverflow::Shrink {
+            let code_style = [Class::Code].iter().into();
+            let code_width = width - gutter(&code_style);
+            shrink_code_block(self.resources, &mut lines, code_width, self.cfg.code_min_font_scale);
+        }
+        // The numbers are set in the size of the code, without its colors
+        let mut number_style = lines
+            .iter()
+            .flat_map(|line| line.iter())
+            .find_map(|span| match span {
+                Span::Text { style, .. } => Some(style.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| [Class::Code].iter().into());
+        if let Some(token) = number_style.token() {
+            number_style.remove(&Class::Token(token));
+        }
+        let gutter = gutter(&number_style);
+        width -= gutter;
+
+        let mut fitted = Vec::new();
+        let mut highlighted = Vec::new();
+        for (idx, line) in lines.into_iter().enumerate() {
+            let number = first_number + idx;
+            let wrapped = wrap_code_line(
+                self.resources,
+                line,
+                width,
+                &self.cfg.code_continuation_marker,
+                self.cfg.code_continuation_indentation,
+            );
+            for (part, mut line) in wrapped.into_iter().enumerate() {
+                if numbered {
+                    let mut gutter_spans = Vec::new();
+                    if part == 0 {
+                        let number = Span::text(number.to_string(), number_style.clone());
+                        let spacing = self.cfg.code_line_number_spacing;
+                        gutter_spans.push(Span::gap(gutter - spacing - number.width(self.resources)));
+                        gutter_spans.push(number);
+                        gutter_spans.push(Span::gap(spacing));
+                    } else {
+                        gutter_spans.push(Span::gap(gutter));
+                    }
+                    line.splice(0..0, gutter_spans);
+                }
+                if info.is_highlighted(number) {
+                    highlighted.push(fitted.len());
+                }
+                fitted.push(line);
+            }
+        }
+        (fitted, highlighted)
+    }
+
+    /// Breaks the text that the optimal line breaker has collected so far,
+    /// keeping the last line open so that more text can be put on it
+    fn break_pending_lines(&mut self) {
+        let alignment = self.line_alignment();
+        let width = self.max_x - self.min_x;
+        let line = std::mem::take(&mut self.current_line);
+        let mut lines = break_lines(self.resources, line, width);
+        let last = lines.pop().unwrap_or_default();
+        for mut line in lines {
+            align_line(self.resources, &mut line, width, alignment, true);
+            self.lines.push(Section::plain(line));
+        }
+        self.x = last
+            .iter()
+            .fold(self.min_x, |x, span| x + span.width(self.resources));
+        self.current_line = last;
+    }
+
+    fn line_alignment(&self) -> TextAlignment {
+        match self.heading {
+            Some(_) => self.cfg.heading_alignment.unwrap_or(self.alignment),
+            None => self.alignment,
+        }
+    }
+
     pub fn get_vec(mut self) -> Vec<Section> {
         // Make sure that current_line is put into the output
-        if self.current_line.len() != 0 {
-            self.lines.push(Section::plain(self.current_line));
-        }
+        self.new_line();
         // Check if the last section is a blank-type of section, so that we
         // don't get an extra page at the end of the document
         if self.lines.last().map(|t| t.is_empty()).unwrap_or(false) {
@@ -193,3 +599,36 @@ impl<'res> Sectioner<'res> {
         self.lines
     }
 }
+
+/// The spacing of headings of the given level, out of spacings for levels 1 to 6
+fn heading_spacing(spacing: &[Mm; 6], level: u8) -> Mm {
+    let idx = (level as usize).max(1).min(spacing.len()) - 1;
+    spacing[idx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlighted_lines(info: &str, lines: usize) -> Vec<usize> {
+        let resources = Resources::new(Config::default());
+        let sectioner = Sectioner::new(Mm(0.0), Mm(100.0), &resources);
+        let style: Style = [Class::Code].iter().into();
+        let lines = (0..lines)
+            .map(|idx| vec![Span::text(format!("line {}", idx), style.clone())])
+            .collect();
+        let (_, highlighted) = sectioner.fit_code_lines(lines, &CodeBlockInfo::parse(info));
+        highlighted
+    }
+
+    #[test]
+    fn highlights_lines_by_their_numbers() {
+        assert_eq!(highlighted_lines("rust {hl=2-3}", 4), vec![1, 2]);
+    }
+
+    #[test]
+    fn highlights_lines_by_the_numbers_they_start_from() {
+        assert_eq!(highlighted_lines("rust {start=10, hl=11}", 4), vec![1]);
+        assert_eq!(highlighted_lines("rust {start=10, hl=3-5}", 4), Vec::<usize>::new());
+    }
+}
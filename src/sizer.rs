@@ -20,6 +20,8 @@ where
 pub enum SizedEvent<'src> {
     SizedAtom(SizedAtom<'src>),
     Break(Break),
+    TableOfContents,
+    TaskListMarker(bool),
     StartBlock(BlockTag),
     EndBlock(BlockTag),
 }
@@ -38,14 +40,13 @@ where
     type Item = SizedEvent<'src>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let event = match self.events.next() {
-            Some(e) => e,
-            None => return None,
-        };
+        let event = self.events.next()?;
         match event {
             AtomizerEvent::StartBlock(block_type) => Some(SizedEvent::StartBlock(block_type)),
             AtomizerEvent::EndBlock(block_type) => Some(SizedEvent::EndBlock(block_type)),
             AtomizerEvent::Break(break_type) => Some(SizedEvent::Break(break_type)),
+            AtomizerEvent::TableOfContents => Some(SizedEvent::TableOfContents),
+            AtomizerEvent::TaskListMarker(checked) => Some(SizedEvent::TaskListMarker(checked)),
 
             AtomizerEvent::Atom(Atom::Text { text, style }) => {
                 let width = width_of_text(self.resources, &style, &text).into();
@@ -53,13 +54,13 @@ where
 
                 let sized_atom = SizedAtom {
                     atom: Atom::Text { text, style },
-                    width: width,
-                    height: height,
+                    width,
+                    height,
                 };
                 Some(SizedEvent::SizedAtom(sized_atom))
             }
 
-            AtomizerEvent::Atom(Atom::Image { uri }) => {
+            AtomizerEvent::Atom(Atom::Image { uri, link }) => {
                 // TODO: Use title, and ignore alt-text
                 // Or should alt-text always be used?
                 if let Some(image) = self.resources.get_image(&uri) {
@@ -69,7 +70,7 @@ where
                         ::printpdf::Px(h as usize).into_pt(IMAGE_DPI).into(),
                     );
                     let sized_atom = SizedAtom {
-                        atom: Atom::Image { uri },
+                        atom: Atom::Image { uri, link },
                         width: w,
                         height: h,
                     };
@@ -77,7 +78,7 @@ where
                 } else {
                     warn!("Couldn't load image: {:?}", uri);
                     let sized_atom = SizedAtom {
-                        atom: Atom::Image { uri },
+                        atom: Atom::Image { uri, link },
                         width: WIDTH_IMAGE_NOT_FOUND,
                         height: HEIGHT_IMAGE_NOT_FOUND,
                     };
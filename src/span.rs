@@ -1,4 +1,4 @@
-use printpdf::Mm;
+use printpdf::{Color, Mm, Rgb};
 use crate::resources::Resources;
 use std::path::PathBuf;
 use crate::style::Style;
@@ -14,10 +14,23 @@ pub enum Span {
         width: Mm,
         height: Mm,
         path: PathBuf,
+        /// The destination of the link around the image, if any
+        link: Option<String>,
     },
+    /// A filled rectangle
     Rect {
         width: Mm,
         height: Mm,
+        color: Color,
+    },
+    /// Empty space, used to align and justify lines
+    Gap {
+        width: Mm,
+    },
+    /// A place where a word may be hyphenated. It takes up no space, unless the
+    /// line is broken there, which turns it into a hyphen.
+    Hyphen {
+        style: Style,
     },
 }
 
@@ -26,31 +39,59 @@ impl Span {
         Span::Text { text, style }
     }
 
-    pub fn image(width: Mm, height: Mm, path: PathBuf) -> Self {
+    pub fn image(width: Mm, height: Mm, path: PathBuf, link: Option<String>) -> Self {
         Span::Image {
             width,
             height,
             path,
+            link,
         }
     }
 
     pub fn rect(width: Mm, height: Mm) -> Self {
-        Span::Rect { width, height }
+        Self::colored_rect(width, height, Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)))
+    }
+
+    pub fn colored_rect(width: Mm, height: Mm, color: Color) -> Self {
+        Span::Rect {
+            width,
+            height,
+            color,
+        }
+    }
+
+    pub fn gap(width: Mm) -> Self {
+        Span::Gap { width }
+    }
+
+    pub fn hyphen(style: Style) -> Self {
+        Span::Hyphen { style }
     }
 
     pub fn width(&self, resources: &Resources) -> Mm {
         match self {
-            Span::Text { text, style, .. } => width_of_text(resources, &style, &text).into(),
-            Span::Image { width, .. } => width.clone(),
-            Span::Rect { width, .. } => width.clone(),
+            Span::Text { text, style, .. } => width_of_text(resources, style, text).into(),
+            Span::Image { width, .. } => *width,
+            Span::Rect { width, .. } => *width,
+            Span::Gap { width } => *width,
+            Span::Hyphen { .. } => Mm(0.0),
+        }
+    }
+
+    /// Whether this is the space between two words, where lines can be broken
+    pub fn is_space(&self) -> bool {
+        match self {
+            Span::Text { text, .. } => text == " ",
+            _ => false,
         }
     }
 
     pub fn height(&self, resources: &Resources) -> Mm {
         match self {
-            Span::Text { style, .. } => font_height(resources, &style).into(),
-            Span::Image { height, .. } => height.clone(),
-            Span::Rect { height, .. } => height.clone(),
+            Span::Text { style, .. } => font_height(resources, style).into(),
+            Span::Image { height, .. } => *height,
+            Span::Rect { height, .. } => *height,
+            Span::Gap { .. } | Span::Hyphen { .. } => Mm(0.0),
         }
     }
 }
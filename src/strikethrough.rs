@@ -0,0 +1,74 @@
+use printpdf::{Mm, Pt};
+use crate::resources::Resources;
+use crate::span::{PositionedSpan, Span};
+use crate::style::Class;
+use crate::util::scale_from_style;
+
+/// How far the line is above the baseline, relative to the font size
+const STRIKETHROUGH_POSITION: f64 = 0.27;
+/// How thick the line is, relative to the font size
+const STRIKETHROUGH_THICKNESS: f64 = 0.05;
+
+/// A line drawn through text
+#[derive(Clone, Debug)]
+pub struct Strikethrough {
+    /// The lower left corner of the line
+    pub pos: (Mm, Mm),
+    pub width: Mm,
+    pub thickness: Mm,
+}
+
+/// Finds the lines that have to be drawn through struck out text on a page.
+/// Struck out words that are next to each other on a line share one line, which
+/// also goes through the space between them.
+pub fn find_strikethroughs(resources: &Resources, spans: &[PositionedSpan]) -> Vec<Strikethrough> {
+    let cfg = resources.get_config();
+    let mut lines = Vec::new();
+    // The line that is currently being extended, and the baseline of its text
+    let mut current: Option<(Mm, Strikethrough)> = None;
+
+    for positioned in spans {
+        let (text, style) = match positioned.span {
+            Span::Text {
+                ref text,
+                ref style,
+            } => (text, style),
+            // Gaps are the stretched space between words
+            Span::Gap { .. } => continue,
+            _ => {
+                lines.extend(current.take().map(|(_, line)| line));
+                continue;
+            }
+        };
+
+        if !style.contains(&Class::Strikethrough) {
+            if !text.trim().is_empty() {
+                lines.extend(current.take().map(|(_, line)| line));
+            }
+            continue;
+        }
+
+        let (x, y) = positioned.pos;
+        let end = x + positioned.span.width(resources);
+        if let Some((baseline, ref mut line)) = current {
+            if baseline == y {
+                line.width = end - line.pos.0;
+                continue;
+            }
+        }
+
+        let font_size: Mm = Pt(f64::from(scale_from_style(cfg, style).y)).into();
+        let thickness = font_size * STRIKETHROUGH_THICKNESS;
+        lines.extend(current.take().map(|(_, line)| line));
+        current = Some((
+            y,
+            Strikethrough {
+                pos: (x, y + font_size * STRIKETHROUGH_POSITION - thickness / 2.0),
+                width: end - x,
+                thickness,
+            },
+        ));
+    }
+    lines.extend(current.map(|(_, line)| line));
+    lines
+}
@@ -1,6 +1,7 @@
+use crate::highlight::Token;
 use std::collections::HashSet;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct Style(HashSet<Class>);
 
 #[derive(Debug, Hash, Clone, Eq, PartialEq)]
@@ -10,10 +11,19 @@ pub enum Class {
     BlockQuote(u8),
     Strong,
     Emphasis,
+    Strikethrough,
     Code,
     Note,
-    Link,
+    /// A link, along with its destination
+    Link(String),
+    /// The marker of a task list item, which is checked if the task is done
+    Checkbox(bool),
     Superscript,
+    /// Text that is set in this font size (in points), instead of the size of
+    /// the rest of its style
+    FontSize(u8),
+    /// A token of highlighted source code
+    Token(Token),
 }
 
 impl Style {
@@ -28,11 +38,47 @@ impl Style {
     pub fn contains(&self, class: &Class) -> bool {
         self.0.contains(class)
     }
-}
 
-impl Default for Style {
-    fn default() -> Self {
-        Style(HashSet::new())
+    /// The destination of the link this style belongs to, if any
+    pub fn link(&self) -> Option<&str> {
+        self.0.iter().find_map(|class| match class {
+            Class::Link(destination) => Some(destination.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The font size this style sets, if any
+    pub fn font_size(&self) -> Option<u8> {
+        self.0.iter().find_map(|class| match class {
+            Class::FontSize(size) => Some(*size),
+            _ => None,
+        })
+    }
+
+    /// How deeply the block quote this style belongs to is nested, starting
+    /// at 1, or `None` outside of block quotes
+    pub fn quote_level(&self) -> Option<u8> {
+        self.0.iter().find_map(|class| match class {
+            Class::BlockQuote(level) => Some(*level),
+            _ => None,
+        })
+    }
+
+    /// The kind of source code token this style belongs to, if any
+    pub fn token(&self) -> Option<Token> {
+        self.0.iter().find_map(|class| match class {
+            Class::Token(token) => Some(*token),
+            _ => None,
+        })
+    }
+
+    /// Whether the task list item this style belongs to is done, or `None` if
+    /// this style doesn't belong to the marker of a task
+    pub fn checkbox(&self) -> Option<bool> {
+        self.0.iter().find_map(|class| match class {
+            Class::Checkbox(checked) => Some(*checked),
+            _ => None,
+        })
     }
 }
 
@@ -0,0 +1,406 @@
+use crate::atomizer::{Atom, BlockTag, Break};
+use crate::cmark::Alignment;
+use printpdf::Mm;
+use crate::resources::Resources;
+use crate::sizer::{SizedAtom, SizedEvent};
+use crate::span::Span;
+use crate::style::Class;
+use crate::util::overlong_split;
+
+#[derive(Clone, Debug)]
+pub struct Table {
+    pub columns: Vec<Column>,
+    pub head: Option<TableRow>,
+    pub rows: Vec<TableRow>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    /// The width available to the text of the column, without padding
+    pub width: Mm,
+    pub alignment: Alignment,
+}
+
+#[derive(Clone, Debug)]
+pub struct TableRow {
+    /// The wrapped lines of each cell in the row
+    pub cells: Vec<Vec<Vec<Span>>>,
+}
+
+/// Collects the contents of a table until the whole table is known, so that the
+/// columns can be sized before any of the cells are wrapped.
+pub struct TableBuilder {
+    alignments: Vec<Alignment>,
+    head: Option<Vec<Vec<CellItem>>>,
+    rows: Vec<Vec<Vec<CellItem>>>,
+    current_row: Vec<Vec<CellItem>>,
+    current_cell: Vec<CellItem>,
+    is_head: bool,
+}
+
+#[derive(Clone, Debug)]
+enum CellItem {
+    Span(Span),
+    WordBreak,
+    LineBreak,
+}
+
+impl Table {
+    /// The total width of the table, including padding and borders
+    pub fn width(&self, resources: &Resources) -> Mm {
+        let cfg = resources.get_config();
+        let padding = cfg.table_cell_padding * 2.0 + cfg.table_border_width;
+        self.columns
+            .iter()
+            .fold(cfg.table_border_width, |acc, c| acc + c.width + padding)
+    }
+
+    pub fn height(&self, resources: &Resources) -> Mm {
+        self.head
+            .iter()
+            .chain(self.rows.iter())
+            .fold(Mm(0.0), |acc, row| acc + row.height(resources))
+    }
+}
+
+impl TableRow {
+    pub fn height(&self, resources: &Resources) -> Mm {
+        let cfg = resources.get_config();
+        let content_height = self
+            .cells
+            .iter()
+            .map(|lines| {
+                lines
+                    .iter()
+                    .map(|line| line_height(resources, line).0 * cfg.line_spacing)
+                    .sum::<f64>()
+            })
+            .fold(0.0, |x, acc| acc.max(x));
+        Mm(content_height) + cfg.table_cell_padding * 2.0 + cfg.table_border_width
+    }
+
+    /// The height of the row if only the first line of every cell is kept
+    pub fn min_height(&self, resources: &Resources) -> Mm {
+        let first_lines = TableRow {
+            cells: self
+                .cells
+                .iter()
+                .map(|lines| lines.iter().take(1).cloned().collect())
+                .collect(),
+        };
+        first_lines.height(resources)
+    }
+
+    /// Splits the row between two lines, so that the first part is no taller
+    /// than `max_height`. Returns `None` as the first part if not even one line
+    /// of the row fits.
+    pub fn split(&self, resources: &Resources, max_height: Mm) -> (Option<TableRow>, TableRow) {
+        let cfg = resources.get_config();
+        let max_content_height =
+            (max_height - cfg.table_cell_padding * 2.0 - cfg.table_border_width).0;
+
+        let mut first = Vec::new();
+        let mut rest = Vec::new();
+        for lines in self.cells.iter() {
+            let mut height = 0.0;
+            let split_idx = lines
+                .iter()
+                .take_while(|line| {
+                    height += line_height(resources, line).0 * cfg.line_spacing;
+                    height <= max_content_height
+                })
+                .count();
+            first.push(lines[..split_idx].to_vec());
+            rest.push(lines[split_idx..].to_vec());
+        }
+
+        let rest = TableRow { cells: rest };
+        if first.iter().all(|lines| lines.is_empty()) {
+            (None, rest)
+        } else {
+            (Some(TableRow { cells: first }), rest)
+        }
+    }
+}
+
+pub fn line_height(resources: &Resources, line: &[Span]) -> Mm {
+    Mm(line
+        .iter()
+        .map(|x| x.height(resources).0)
+        .fold(0.0, |x, acc| acc.max(x)))
+}
+
+pub fn line_width(resources: &Resources, line: &[Span]) -> Mm {
+    line.iter()
+        .fold(Mm(0.0), |acc, span| acc + span.width(resources))
+}
+
+impl TableBuilder {
+    pub fn new(alignments: Vec<Alignment>) -> Self {
+        Self {
+            alignments,
+            head: None,
+            rows: Vec::new(),
+            current_row: Vec::new(),
+            current_cell: Vec::new(),
+            is_head: false,
+        }
+    }
+
+    pub fn parse_event(&mut self, event: SizedEvent) {
+        match event {
+            SizedEvent::StartBlock(BlockTag::TableHead) => self.is_head = true,
+            SizedEvent::EndBlock(BlockTag::TableHead) => {
+                self.head = Some(self.current_row.split_off(0));
+                self.is_head = false;
+            }
+            SizedEvent::StartBlock(BlockTag::TableRow) => {}
+            SizedEvent::EndBlock(BlockTag::TableRow) => {
+                self.rows.push(self.current_row.split_off(0));
+            }
+            SizedEvent::StartBlock(BlockTag::TableCell) => {}
+            SizedEvent::EndBlock(BlockTag::TableCell) => {
+                self.current_row.push(self.current_cell.split_off(0));
+            }
+
+            SizedEvent::Break(Break::Word) => self.current_cell.push(CellItem::WordBreak),
+            SizedEvent::Break(Break::Line) => self.current_cell.push(CellItem::LineBreak),
+
+            SizedEvent::SizedAtom(SizedAtom {
+                atom,
+                width,
+                height,
+            }) => {
+                let span = match atom {
+                    Atom::Text { text, mut style } => {
+                        if self.is_head {
+                            style.insert(Class::Strong);
+                        }
+                        Span::text(text.into_owned(), style)
+                    }
+                    Atom::Image { uri, link } => {
+                        Span::image(width, height, uri.into_owned().into(), link)
+                    }
+                };
+                self.current_cell.push(CellItem::Span(span));
+            }
+
+            event => warn!("Unexpected event inside of table: {:?}", event),
+        }
+    }
+
+    /// Sizes the columns to fit inside of `max_width` and wraps the text of
+    /// every cell to the width of its column.
+    pub fn build(self, resources: &Resources, max_width: Mm) -> Table {
+        let cfg = resources.get_config();
+        let num_columns = self
+            .head
+            .iter()
+            .chain(self.rows.iter())
+            .map(|row| row.len())
+            .fold(self.alignments.len(), usize::max);
+
+        let mut min_widths = vec![0.0; num_columns];
+        let mut max_widths = vec![0.0; num_columns];
+        for row in self.head.iter().chain(self.rows.iter()) {
+            for (idx, cell) in row.iter().enumerate() {
+                let (min, max) = cell_widths(resources, cell);
+                min_widths[idx] = min.0.max(min_widths[idx]);
+                max_widths[idx] = max.0.max(max_widths[idx]);
+            }
+        }
+
+        let padding = cfg.table_cell_padding * 2.0 + cfg.table_border_width;
+        // Tables with more columns than fit get no room for their text at all
+        let available = (max_width - cfg.table_border_width - padding * num_columns as f64)
+            .0
+            .max(0.0);
+        // Words that are wider than an even share of the table are broken up,
+        // so they don't take the room of the other columns
+        let share = available / num_columns.max(1) as f64;
+        let min_widths: Vec<f64> = min_widths.into_iter().map(|w| w.min(share)).collect();
+        let min_total: f64 = min_widths.iter().sum();
+        let max_total: f64 = max_widths.iter().sum();
+
+        let widths: Vec<f64> = if max_total <= available {
+            max_widths
+        } else if min_total >= available && min_total > 0.0 {
+            // Not even the longest words fit, so squeeze every column equally
+            min_widths
+                .iter()
+                .map(|w| w * available / min_total)
+                .collect()
+        } else if min_total >= available {
+            vec![available / num_columns as f64; num_columns]
+        } else {
+            // Give every column its longest word, and distribute the remaining
+            // space according to how much each column would like to have
+            let extra = (available - min_total) / (max_total - min_total);
+            min_widths
+                .iter()
+                .zip(max_widths.iter())
+                .map(|(min, max)| min + (max - min) * extra)
+                .collect()
+        };
+
+        let columns: Vec<Column> = widths
+            .into_iter()
+            .enumerate()
+            .map(|(idx, width)| Column {
+                width: Mm(width),
+                alignment: self.alignments.get(idx).cloned().unwrap_or(Alignment::None),
+            })
+            .collect();
+
+        let wrap_row = |row: Vec<Vec<CellItem>>| TableRow {
+            cells: columns
+                .iter()
+                .zip(row.into_iter().chain(std::iter::repeat(Vec::new())))
+                .map(|(column, cell)| wrap_cell(resources, cell, column.width))
+                .collect(),
+        };
+
+        Table {
+            head: self.head.map(&wrap_row),
+            rows: self.rows.into_iter().map(&wrap_row).collect(),
+            columns,
+        }
+    }
+}
+
+/// Returns the width of the longest word, and the width of the longest line
+/// of the cell when it is not wrapped at all.
+fn cell_widths(resources: &Resources, cell: &[CellItem]) -> (Mm, Mm) {
+    let lines = wrap_cell(resources, cell.to_vec(), Mm(f64::INFINITY));
+    let max = lines
+        .iter()
+        .map(|line| line_width(resources, line).0)
+        .fold(0.0, f64::max);
+    let min = cell
+        .split(|item| !matches!(item, CellItem::Span(_)))
+        .map(|word| {
+            word.iter()
+                .map(|item| match item {
+                    CellItem::Span(span) => span.width(resources).0,
+                    _ => 0.0,
+                })
+                .sum::<f64>()
+        })
+        .fold(0.0, f64::max);
+    (Mm(min), Mm(max))
+}
+
+fn wrap_cell(resources: &Resources, cell: Vec<CellItem>, max_width: Mm) -> Vec<Vec<Span>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<Span> = Vec::new();
+    let mut x = Mm(0.0);
+    let mut pending_space = false;
+
+    for item in cell {
+        match item {
+            CellItem::WordBreak => pending_space = !line.is_empty(),
+            CellItem::LineBreak => {
+                lines.push(line.split_off(0));
+                x = Mm(0.0);
+                pending_space = false;
+            }
+            CellItem::Span(mut span) => {
+                let mut width = span.width(resources);
+                if pending_space {
+                    let space = Span::text(" ".into(), Default::default());
+                    let space_width = space.width(resources);
+                    if x + space_width + width > max_width {
+                        lines.push(line.split_off(0));
+                        x = Mm(0.0);
+                    } else {
+                        x += space_width;
+                        line.push(space);
+                    }
+                    pending_space = false;
+                }
+                // Words that are wider than the column are broken wherever they
+                // reach its edge
+                while x + width > max_width {
+                    let (text, style) = match span {
+                        Span::Text { ref text, ref style } => (text.clone(), style.clone()),
+                        _ => break,
+                    };
+                    let split = overlong_split(resources, &style, &text, max_width - x);
+                    if split >= text.len() {
+                        break;
+                    }
+                    line.push(Span::text(text[..split].to_string(), style.clone()));
+                    lines.push(line.split_off(0));
+                    x = Mm(0.0);
+                    span = Span::text(text[split..].to_string(), style);
+                    width = span.width(resources);
+                }
+                x += width;
+                line.push(span);
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn cell(words: &[&str]) -> Vec<CellItem> {
+        let mut items = Vec::new();
+        for (idx, word) in words.iter().enumerate() {
+            if idx > 0 {
+                items.push(CellItem::WordBreak);
+            }
+            items.push(CellItem::Span(Span::text(word.to_string(), Default::default())));
+        }
+        items
+    }
+
+    fn text(line: &[Span]) -> String {
+        line.iter()
+            .map(|span| match span {
+                Span::Text { text, .. } => text.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn breaks_up_words_wider_than_the_column() {
+        let resources = Resources::new(Config::default());
+        let word = "a".repeat(70);
+        let lines = wrap_cell(&resources, cell(&["see", &word]), Mm(20.0));
+
+        assert_eq!(text(&lines[0]), "see");
+        assert!(lines.len() > 2);
+        for line in lines.iter() {
+            assert!(line_width(&resources, line) <= Mm(20.0));
+        }
+        let rest: String = lines[1..].iter().map(|line| text(line)).collect();
+        assert_eq!(rest, word);
+    }
+
+    #[test]
+    fn keeps_long_words_from_taking_the_room_of_other_columns() {
+        let resources = Resources::new(Config::default());
+        let mut builder = TableBuilder::new(vec![Alignment::None, Alignment::None]);
+        let word = "a".repeat(200);
+        builder.rows.push(vec![cell(&["Name"]), cell(&[&word])]);
+        let table = builder.build(&resources, Mm(150.0));
+
+        assert!(table.width(&resources) <= Mm(150.0));
+        let row = &table.rows[0];
+        assert_eq!(row.cells[0].len(), 1, "the short word is kept whole");
+        for (column, lines) in table.columns.iter().zip(row.cells.iter()) {
+            for line in lines {
+                assert!(line_width(&resources, line) <= column.width);
+            }
+        }
+    }
+}
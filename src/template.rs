@@ -0,0 +1,128 @@
+use crate::page::Page;
+use printpdf::Mm;
+use crate::resources::Resources;
+use crate::span::Span;
+use crate::style::Style;
+use crate::util::font_height;
+
+/// A line of text repeated on every page, like a header or a footer. Each slot
+/// can contain the placeholders `{page}`, `{pages}`, `{title}` and `{section}`.
+#[derive(Clone, Debug, Default)]
+pub struct PageTemplate {
+    pub left: String,
+    pub center: String,
+    pub right: String,
+}
+
+/// The values that are put in place of the placeholders of a `PageTemplate`
+pub struct TemplateValues<'a> {
+    /// The number of the page, starting at 1
+    pub page: usize,
+    /// The total number of pages
+    pub pages: usize,
+    pub title: &'a str,
+    /// The text of the heading that the page belongs to
+    pub section: &'a str,
+}
+
+impl PageTemplate {
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty() && self.center.is_empty() && self.right.is_empty()
+    }
+
+    /// The space taken away from the body of every page, including the
+    /// spacing between the template and the body
+    pub fn height(&self, resources: &Resources) -> Mm {
+        if self.is_empty() {
+            return Mm(0.0);
+        }
+        let cfg = resources.get_config();
+        let line_height: Mm = font_height(resources, &Style::default()).into();
+        line_height * cfg.line_spacing + cfg.page_template_spacing
+    }
+
+    /// Fills in the placeholders and puts the slots on `page`, with `y` as the
+    /// baseline
+    pub fn render(&self, resources: &Resources, page: &mut Page, y: Mm, values: &TemplateValues) {
+        let cfg = resources.get_config();
+        let min_x = cfg.margin.0;
+        let max_x = cfg.page_size.0 - cfg.margin.0;
+
+        let slots = [(&self.left, 0.0), (&self.center, 0.5), (&self.right, 1.0)];
+        for &(template, alignment) in slots.iter() {
+            if template.is_empty() {
+                continue;
+            }
+            let span = Span::text(fill(template, values), Style::default());
+            let x = min_x + (max_x - min_x - span.width(resources)) * alignment;
+            page.render_spans(resources, &[span], x, y);
+        }
+    }
+}
+
+/// Puts the values in place of the placeholders of `template`. The template is
+/// read in a single pass, so placeholders inside of the values are left alone.
+/// Unknown placeholders are kept as they are.
+fn fill(template: &str, values: &TemplateValues) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[1..end] {
+            "page" => filled.push_str(&values.page.to_string()),
+            "pages" => filled.push_str(&values.pages.to_string()),
+            "title" => filled.push_str(values.title),
+            "section" => filled.push_str(values.section),
+            _ => {
+                // Not a placeholder, so the brace is kept and the search goes
+                // on after it
+                filled.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<'a>(title: &'a str, section: &'a str) -> TemplateValues<'a> {
+        TemplateValues {
+            page: 3,
+            pages: 12,
+            title,
+            section,
+        }
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        let values = values("Manual", "Install");
+        assert_eq!(fill("Page {page} of {pages}", &values), "Page 3 of 12");
+        assert_eq!(fill("{title}: {section}", &values), "Manual: Install");
+    }
+
+    #[test]
+    fn leaves_placeholders_in_values_alone() {
+        let values = values("Using {section}", "The {pages} field");
+        assert_eq!(fill("{title} / {section}", &values), "Using {section} / The {pages} field");
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders_and_braces() {
+        let values = values("Manual", "Install");
+        assert_eq!(fill("{chapter} {page}", &values), "{chapter} 3");
+        assert_eq!(fill("{{page}}", &values), "{3}");
+        assert_eq!(fill("{page", &values), "{page");
+    }
+}
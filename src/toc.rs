@@ -0,0 +1,113 @@
+use crate::alignment::TextAlignment;
+use crate::atomizer::Break;
+use crate::heading::{Heading, HeadingPosition};
+use printpdf::Mm;
+use crate::resources::Resources;
+use crate::section::Section;
+use crate::sectioner::Sectioner;
+use crate::sizer::SizedEvent;
+use crate::style::{Class, Style};
+use crate::util::width_of_text;
+
+/// A line (or several, if the heading is long) of the table of contents
+#[derive(Clone, Debug)]
+pub struct TocEntry {
+    pub anchor: String,
+    /// How far the entry is indented, relative to the table of contents
+    pub indentation: Mm,
+    /// The text of the heading, wrapped so that there is room left for the page
+    /// number
+    pub lines: Vec<Section>,
+    /// The style of the dot leader and the page number
+    pub style: Style,
+    /// The number of the page the heading is on, once the document has been
+    /// laid out
+    pub page: Option<usize>,
+}
+
+/// Builds the table of contents for `sections`, to fit between `min_x` and
+/// `max_x`. Only headings up to `Config::table_of_contents_depth` are listed.
+pub fn table_of_contents(
+    resources: &Resources,
+    sections: &[Section],
+    min_x: Mm,
+    max_x: Mm,
+) -> Vec<Section> {
+    let cfg = resources.get_config();
+    let mut headings = Vec::new();
+    collect_headings(sections, cfg.table_of_contents_depth, &mut headings);
+
+    let mut toc = Vec::new();
+    if let Some(ref title) = cfg.table_of_contents_title {
+        let style: Style = [Class::Heading(2)].iter().into();
+        toc.extend(wrap(resources, title, &style, min_x, max_x));
+        toc.push(Section::space(cfg.section_spacing));
+    }
+
+    let top_level = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    for heading in headings {
+        let mut style = Style::default();
+        style.insert(Class::Link(format!("#{}", heading.anchor)));
+        if heading.level == top_level {
+            style.insert(Class::Strong);
+        }
+
+        // Leave room for a short dot leader and a page number of four digits
+        let number_width: Mm = width_of_text(resources, &style, " ...9999").into();
+        let indentation = cfg.list_indentation * f64::from(heading.level - top_level);
+        let lines = wrap(
+            resources,
+            &heading.text,
+            &style,
+            min_x + indentation,
+            max_x - number_width,
+        );
+
+        toc.push(Section::toc_entry(TocEntry {
+            anchor: heading.anchor,
+            indentation,
+            lines,
+            style,
+            page: None,
+        }));
+    }
+    toc
+}
+
+/// Fills in the page numbers of the entries in `sections`, using the pages that
+/// the headings were put on
+pub fn set_page_numbers(sections: &mut [Section], headings: &[HeadingPosition]) {
+    for section in sections.iter_mut() {
+        if let Section::TocEntry(ref mut entry) = section {
+            entry.page = headings
+                .iter()
+                .find(|p| p.heading.anchor == entry.anchor)
+                .map(|p| p.page + 1);
+        }
+    }
+}
+
+fn collect_headings(sections: &[Section], max_level: u8, headings: &mut Vec<Heading>) {
+    for section in sections {
+        match section {
+            Section::Heading(heading, _lines) if heading.level <= max_level => {
+                headings.push(heading.clone())
+            }
+            Section::ListItem(_, _, sections) | Section::BlockQuote(sections) => {
+                collect_headings(sections, max_level, headings)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn wrap(resources: &Resources, text: &str, style: &Style, min_x: Mm, max_x: Mm) -> Vec<Section> {
+    let mut sectioner = Sectioner::new(min_x, max_x, resources);
+    // The page numbers are already lined up at the right margin
+    sectioner.alignment = TextAlignment::Left;
+    for word in text.split_whitespace() {
+        sectioner.parse_event(SizedEvent::Break(Break::Word));
+        sectioner.write_wrapped(word, style);
+    }
+    sectioner.get_vec()
+}
@@ -1,4 +1,5 @@
-use printpdf::Pt;
+use crate::cmark::CowStr;
+use printpdf::{Mm, Pt};
 use crate::resources::Resources;
 use rusttype::{Font, Scale};
 use std::borrow::Cow;
@@ -29,10 +30,20 @@ pub fn font_height(resources: &Resources, style: &Style) -> Pt {
     Pt(height)
 }
 
+/// The distance from the baseline to the top and the bottom of the font. The
+/// descent is below the baseline, so it is negative.
+pub fn font_ascent_descent(resources: &Resources, style: &Style) -> (Pt, Pt) {
+    let font = font_from_style(resources, style);
+    let scale = scale_from_style(resources.get_config(), style);
+    let v_metrics = font.v_metrics(scale);
+    (Pt(v_metrics.ascent as f64), Pt(v_metrics.descent as f64))
+}
+
 pub fn font_from_style<'res>(resources: &'res Resources, style: &Style) -> &'res Font<'res> {
     let config = resources.get_config();
     let strong = style.contains(&Class::Strong);
-    let emphasis = style.contains(&Class::Emphasis);
+    let emphasis = style.contains(&Class::Emphasis)
+        || (config.quote_italic && style.quote_level().is_some());
 
     if style.contains(&Class::Code) {
         resources
@@ -57,8 +68,21 @@ pub fn font_from_style<'res>(resources: &'res Resources, style: &Style) -> &'res
     }
 }
 
+/// How much smaller superscript text is than the text around it
+const SUPERSCRIPT_SCALE: f32 = 0.6;
+/// How far superscript text is raised, relative to its own size
+const SUPERSCRIPT_RISE: f64 = 0.6;
+
 pub fn scale_from_style(config: &Config, style: &Style) -> Scale {
-    if style.contains(&Class::Heading(4)) {
+    let scale = if let Some(size) = style.font_size() {
+        Scale::uniform(f32::from(size))
+    } else if style.contains(&Class::Note) {
+        config.footnote_font_size
+    } else if style.contains(&Class::Heading(6)) {
+        config.h6_font_size
+    } else if style.contains(&Class::Heading(5)) {
+        config.h5_font_size
+    } else if style.contains(&Class::Heading(4)) {
         config.h4_font_size
     } else if style.contains(&Class::Heading(3)) {
         config.h3_font_size
@@ -68,6 +92,33 @@ pub fn scale_from_style(config: &Config, style: &Style) -> Scale {
         config.h1_font_size
     } else {
         config.default_font_size
+    };
+    if style.contains(&Class::Superscript) {
+        Scale {
+            x: scale.x * SUPERSCRIPT_SCALE,
+            y: scale.y * SUPERSCRIPT_SCALE,
+        }
+    } else {
+        scale
+    }
+}
+
+/// How far the baseline of text in this style is above the baseline of the
+/// line it is on
+pub fn baseline_offset(config: &Config, style: &Style) -> Pt {
+    if style.contains(&Class::Superscript) {
+        Pt(f64::from(scale_from_style(config, style).y) * SUPERSCRIPT_RISE)
+    } else {
+        Pt(0.0)
+    }
+}
+
+/// Converts the string type of the markdown parser into a `Cow`, without
+/// copying borrowed strings
+pub fn cow_from_cmark(text: CowStr) -> Cow<str> {
+    match text {
+        CowStr::Borrowed(text) => Cow::Borrowed(text),
+        text => Cow::Owned(text.into_string()),
     }
 }
 
@@ -84,3 +135,51 @@ pub fn slice_cow_till_idx<'c>(text: &Cow<'c, str>, idx: usize) -> Cow<'c, str> {
         Cow::Borrowed(stringref) => Cow::Borrowed(&stringref[..idx]),
     }
 }
+
+/// Where to break a word that doesn't fit into `available`, as an index into
+/// `word`. The break is put after the last `/`, `-`, `_` or `.` that fits, so
+/// that URLs and paths are broken between their parts, or else after the last
+/// character that fits. At least one character is always kept, so that breaking
+/// up a word always makes progress.
+pub fn overlong_split(resources: &Resources, style: &Style, word: &str, available: Mm) -> usize {
+    let mut width = Mm(0.0);
+    let mut last_fitting = None;
+    let mut last_preferred = None;
+    let mut chars = word.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let end = idx + c.len_utf8();
+        width += width_of_text(resources, style, &c.to_string()).into();
+        if width > available {
+            break;
+        }
+        if end == word.len() {
+            return end;
+        }
+        // Combining marks and joiners belong to the character before them
+        let next = chars.peek().map(|&(_, next)| next);
+        if next.map(joins_previous).unwrap_or(false) || c == '\u{200D}' {
+            continue;
+        }
+        last_fitting = Some(end);
+        if ['/', '-', '_', '.'].contains(&c) {
+            last_preferred = Some(end);
+        }
+    }
+    last_preferred
+        .or(last_fitting)
+        .unwrap_or_else(|| word.chars().next().map_or(word.len(), char::len_utf8))
+}
+
+/// Whether `c` is drawn together with the character before it, like combining
+/// accents, zero width joiners and variation selectors
+fn joins_previous(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{200D}'
+    )
+}